@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use config::LightingConfig;
+use status::{SharedStatus, Status};
+
+
+// Seat lighting over Art-Net. Every frame each fixture gets red, green, blue and a
+// dimmer from its headset's state: silent, waiting in single or paired (both seats of
+// a pair share a colour, picked by the lower voice so it stays put while other pairs
+// come and go), with the dimmer following the rms level.
+
+const PORT_DMX_LENGTH: usize = 512;
+const MIN_DB: f64 = -60f64; // rms at or below this leaves the dimmer at min_dimmer
+
+
+pub type Universe = u16;
+
+
+fn dimmer(config: &LightingConfig, rms: Option<f64>) -> u8 {
+    let level = ((rms.unwrap_or(MIN_DB) - MIN_DB) / -MIN_DB).max(0f64).min(1f64);
+    let min = config.min_dimmer as f64;
+    (min + (255f64 - min) * level).round() as u8
+}
+
+
+// dmx values for every universe with a fixture in it
+pub fn frame(config: &LightingConfig, status: &Status) -> BTreeMap<Universe, Vec<u8>> {
+    let mut universes = BTreeMap::new();
+    for fixture in &config.fixtures {
+        let data = universes.entry(fixture.universe).or_insert(vec![0u8; PORT_DMX_LENGTH]);
+        let voice = status.voices.iter().find(|&(_, v)| v.id == fixture.headset);
+        let (rgb, dim) = match voice {
+            None => continue, // not plugged in, dark
+            Some((_, v)) if v.offline => continue,
+            Some((voice, v)) => {
+                match status.pairs.iter().find(|&&(a, b)| a == *voice || b == *voice) {
+                    Some(&(a, b)) => (config.pairs[a.min(b) % config.pairs.len()], dimmer(config, v.rms)),
+                    None if status.single == Some(*voice) => (config.single, dimmer(config, v.rms)),
+                    None => (config.silent, config.min_dimmer),
+                }
+            },
+        };
+        let start = fixture.channel as usize - 1;
+        data[start..start + 3].copy_from_slice(&rgb);
+        data[start + 3] = dim;
+    }
+    universes
+}
+
+
+// an ArtDmx packet, protocol version 14
+pub fn packet(universe: Universe, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = b"Art-Net\0".to_vec();
+    packet.extend_from_slice(&[0x00, 0x50]); // OpDmx, little endian
+    packet.extend_from_slice(&[0, 14]);
+    packet.push(sequence);
+    packet.push(0); // physical port
+    packet.push((universe & 0xff) as u8); // sub-net and universe
+    packet.push((universe >> 8) as u8 & 0x7f); // net
+    packet.push((data.len() >> 8) as u8);
+    packet.push(data.len() as u8);
+    packet.extend_from_slice(data);
+    packet
+}
+
+
+// Send a frame per 1 / fps from a thread of its own. The config can be swapped while
+// running, an empty target sends nothing.
+pub fn run(config: Arc<Mutex<LightingConfig>>, status: SharedStatus) -> Result<(), String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    thread::spawn(move || {
+        let mut sequence = 1u8;
+        loop {
+            let config = config.lock().unwrap().clone();
+            if config.target.len() > 0 {
+                let status = status.lock().unwrap().clone();
+                for (universe, data) in frame(&config, &status) {
+                    match socket.send_to(&packet(universe, sequence, &data), &config.target[..]) {
+                        Ok(_) => {},
+                        Err(e) => warn!("art-net: {}: {}", config.target, e),
+                    }
+                }
+                // 0 means sequencing is off
+                sequence = if sequence == 255 { 1 } else { sequence + 1 };
+            }
+            thread::sleep(Duration::from_millis((1000f64 / config.fps) as u64));
+        }
+    });
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use config::{FixtureConfig, LightingConfig};
+    use status::{Status, VoiceStatus};
+    use super::{frame, packet, run};
+
+    fn fixture(headset: &str, universe: u16, channel: u16) -> FixtureConfig {
+        FixtureConfig { headset: String::from(headset), universe: universe, channel: channel }
+    }
+
+    fn voice(id: &str, rms: f64) -> VoiceStatus {
+        VoiceStatus { id: String::from(id), rms: Some(rms), ..VoiceStatus::default() }
+    }
+
+    fn config() -> LightingConfig {
+        LightingConfig {
+            fixtures: vec![fixture("a", 0, 1), fixture("b", 0, 5), fixture("c", 1, 1), fixture("d", 1, 5), fixture("gone", 0, 9)],
+            ..LightingConfig::default()
+        }
+    }
+
+    fn status() -> Status {
+        let mut status = Status::default();
+        status.voices.insert(0, voice("a", -60.0));
+        status.voices.insert(1, voice("b", 0.0));
+        status.voices.insert(2, voice("c", -30.0));
+        status.voices.insert(3, voice("d", -30.0));
+        status.pairs = vec![(0, 1)];
+        status.single = Some(2);
+        status
+    }
+
+    #[test]
+    fn test_frame() {
+        let config = config();
+        let universes = frame(&config, &status());
+        assert_eq!(universes.len(), 2);
+        let zero = &universes[&0];
+        // paired seats share a colour, the dimmer follows the level
+        assert_eq!(&zero[0..4], &[255, 0, 0, 64]);
+        assert_eq!(&zero[4..8], &[255, 0, 0, 255]);
+        assert_eq!(&zero[8..12], &[0, 0, 0, 0]);
+        let one = &universes[&1];
+        assert_eq!(&one[0..4], &[255, 160, 0, 160]);
+        assert_eq!(&one[4..8], &[0, 0, 64, 64]);
+
+        // c and d keep their colour when a and b hang up
+        let mut status = status();
+        status.pairs = vec![(0, 1), (2, 3)];
+        status.single = None;
+        assert_eq!(&frame(&config, &status)[&1][0..3], &[0, 128, 255]);
+        status.pairs = vec![(2, 3)];
+        assert_eq!(&frame(&config, &status)[&1][0..3], &[0, 128, 255]);
+    }
+
+    #[test]
+    fn test_packet() {
+        let p = packet(0x123, 7, &[1, 2, 3, 4]);
+        assert_eq!(&p[..8], b"Art-Net\0");
+        assert_eq!(&p[8..18], &[0x00, 0x50, 0, 14, 7, 0, 0x23, 0x01, 0, 4]);
+        assert_eq!(&p[18..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_udp() {
+        // a stand-in for the art-net node
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = LightingConfig { target: node.local_addr().unwrap().to_string(), fixtures: vec![fixture("a", 3, 1)], ..config() };
+        run(Arc::new(Mutex::new(config)), Arc::new(Mutex::new(status()))).unwrap();
+        let mut buf = [0u8; 1024];
+        let len = node.recv(&mut buf).unwrap();
+        assert_eq!(len, 18 + 512);
+        assert_eq!(buf[14], 3);
+        assert_eq!(&buf[18..22], &[255, 0, 0, 64]);
+    }
+}
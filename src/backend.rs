@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+extern crate gst;
+use gst::ElementT;
+
+use gst_helpers::{gst_finalize_pipeline_later, gst_message_get_double, gst_message_get_name};
+use headset::Headset;
+
+
+// What egloorator needs from the audio system: a level reading per level interval from
+// every headset, routes from one headset's source to another's sink and the beep that
+// tells a speaker they were heard. GstBackend is the real thing, SimBackend in
+// simulation.rs plays scripted levels on a virtual clock.
+pub trait AudioBackend: Send + Sync {
+    fn level_source(&self, headset: &Headset, level_interval: f64) -> Result<Box<LevelSource>, String>;
+    // failed may be called from any thread once the route breaks
+    fn route(&self, speaker: &Headset, listener: &Headset, failed: Box<Fn(String) + Send>) -> Result<Box<Route>, String>;
+    // None if the headset cannot be beeped
+    fn cue(&self, headset: &Headset, amplitude: f64) -> Option<Box<Cue>>;
+}
+
+
+pub type SharedBackend = Arc<AudioBackend>;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelEvent {
+    Level(f64), // rms, dB
+    Eos,
+    Error(String),
+    Closed, // nothing more will come, stop quietly
+}
+
+
+pub trait LevelSource {
+    // None when nothing came within timeout
+    fn next(&mut self, timeout: Duration) -> Option<LevelEvent>;
+}
+
+
+pub trait Route {
+    fn stop(self: Box<Self>);
+}
+
+
+pub trait Cue {
+    fn play(&mut self);
+    fn pause(&mut self);
+}
+
+
+pub struct GstBackend;
+
+
+fn make_level_pipeline(source: &String, level_interval: f64) -> String {
+    format!("{} ! level interval={} ! fakesink", source, level_interval)
+}
+
+
+fn make_simplex_pipeline(speaker: &Headset, listener: &Headset) -> String {
+    let amplification = speaker.amplification;
+    debug!("amplifying {} by {}", speaker.id, amplification);
+    format!("{} ! audioamplify amplification={} ! {}", speaker.source, amplification, listener.sink)
+}
+
+
+struct GstLevelSource {
+    pipeline: gst::Pipeline,
+    bus: Receiver<gst::Message>,
+}
+
+
+impl LevelSource for GstLevelSource {
+    fn next(&mut self, timeout: Duration) -> Option<LevelEvent> {
+        let message = match self.bus.recv_timeout(timeout) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => return Some(LevelEvent::Closed),
+        };
+        match message.parse() {
+            gst::Message::ErrorParsed{ref error, ref debug, ..} =>
+                Some(LevelEvent::Error(format!("error from element `{}`: {} (debug: {:?})", message.src_name(), error.message(), debug))),
+            gst::Message::Eos(_) => Some(LevelEvent::Eos),
+            _ => {
+                // level sends messages, look for rms, peak and decay doubles in the structure
+                match gst_message_get_name(&message) {
+                    Some(ref name) if name == "level" => Some(LevelEvent::Level(gst_message_get_double(&message, "rms"))),
+                    Some(_) => None,
+                    None => {
+                        debug!("msg of type `{}` from element `{}`", message.type_name(), message.src_name());
+                        None
+                    },
+                }
+            },
+        }
+    }
+}
+
+
+impl Drop for GstLevelSource {
+    fn drop(&mut self) {
+        self.pipeline.set_null_state();
+    }
+}
+
+
+// A live speaker -> listener pipeline and the thread watching its bus
+struct GstRoute {
+    pipe: gst::Pipeline,
+    stop: Arc<AtomicBool>,
+    to_file: bool,
+}
+
+
+// Forward errors from a route's bus, until the route is torn down
+fn watch_route(receiver: Receiver<gst::Message>, failed: Box<Fn(String) + Send>, stop: Arc<AtomicBool>)
+{
+    while !stop.load(Ordering::SeqCst) {
+        let message = match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        match message.parse() {
+            gst::Message::ErrorParsed{ref error, ref debug, ..} => {
+                failed(format!("error from element `{}`: {} (debug: {:?})", message.src_name(), error.message(), debug));
+                return;
+            },
+            _ => {}
+        }
+    }
+}
+
+
+impl Route for GstRoute {
+    fn stop(self: Box<Self>) {
+        let mut route = *self;
+        route.stop.store(true, Ordering::SeqCst);
+        // files need an EOS to get a valid header, devices can just stop
+        if route.to_file {
+            gst_finalize_pipeline_later(route.pipe, || {});
+        } else {
+            route.pipe.set_null_state();
+        }
+    }
+}
+
+
+struct GstCue {
+    pipeline: gst::Pipeline,
+}
+
+
+impl Cue for GstCue {
+    fn play(&mut self) {
+        self.pipeline.play();
+    }
+
+    fn pause(&mut self) {
+        self.pipeline.pause();
+    }
+}
+
+
+impl Drop for GstCue {
+    fn drop(&mut self) {
+        self.pipeline.set_null_state();
+    }
+}
+
+
+impl AudioBackend for GstBackend {
+    fn level_source(&self, headset: &Headset, level_interval: f64) -> Result<Box<LevelSource>, String> {
+        let s = make_level_pipeline(&headset.source, level_interval);
+        let mut pipeline = gst::Pipeline::new_from_str(&s).map_err(|e| format!("cannot create level pipeline: {}", e.message()))?;
+        let bus = pipeline.bus().ok_or(format!("no bus on level pipeline `{}`", s))?.receiver();
+        pipeline.play();
+        Ok(Box::new(GstLevelSource { pipeline: pipeline, bus: bus }))
+    }
+
+    fn route(&self, speaker: &Headset, listener: &Headset, failed: Box<Fn(String) + Send>) -> Result<Box<Route>, String> {
+        let s = make_simplex_pipeline(speaker, listener);
+        let mut pipe = gst::Pipeline::new_from_str(&*s).map_err(|e| format!("cannot create route `{}`: {}", s, e.message()))?;
+        let receiver = pipe.bus().ok_or(format!("no bus on route `{}`", s))?.receiver();
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher_stop = stop.clone();
+        thread::spawn(move || watch_route(receiver, failed, watcher_stop));
+        pipe.play();
+        Ok(Box::new(GstRoute { pipe: pipe, stop: stop, to_file: listener.sink.contains("filesink") }))
+    }
+
+    fn cue(&self, headset: &Headset, amplitude: f64) -> Option<Box<Cue>> {
+        if !headset.sink.starts_with("pulsesink") {
+            return None;
+        }
+        let s = format!("ladspasrc-sine-so-sine-fcac amplitude={} ! {}", amplitude, headset.sink);
+        match gst::Pipeline::new_from_str(&s) {
+            Ok(pipeline) => Some(Box::new(GstCue { pipeline: pipeline })),
+            Err(e) => {
+                warn!("{}: cannot create cue `{}`: {}", headset.id, s, e.message());
+                None
+            },
+        }
+    }
+}
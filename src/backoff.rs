@@ -0,0 +1,48 @@
+use std::cmp;
+use std::time::Duration;
+
+
+// Exponential backoff between pipeline restarts: initial, 2 * initial, ... up to max
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            next: initial,
+        }
+    }
+
+    pub fn next(&mut self) -> Duration {
+        let current = self.next;
+        self.next = cmp::min(current * 2, self.max);
+        current
+    }
+
+    // after the pipeline has been healthy for a while
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::Backoff;
+
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays = (0..5).map(|_| b.next().as_secs()).collect::<Vec<u64>>();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        b.reset();
+        assert_eq!(b.next(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use headset::HeadsetId;
+use levels::get_levels;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub s2a: f64, // silent to active threshold, dB
+    pub a2s: f64, // active to silent threshold, dB
+}
+
+
+impl Calibration {
+    // bring every headset to the same loudness (target, dB) for the listener
+    pub fn amplification(&self, target: f64) -> f64 {
+        target - self.s2a
+    }
+}
+
+
+// Per headset thresholds, keyed by headset id and kept in a json file. Headsets not in
+// the store fall back to the [[levels]] of the config, then the per model table in levels.rs.
+pub struct CalibrationStore {
+    path: Option<PathBuf>,
+    headsets: BTreeMap<HeadsetId, Calibration>,
+}
+
+
+impl CalibrationStore {
+    pub fn new() -> CalibrationStore {
+        CalibrationStore {
+            path: None,
+            headsets: BTreeMap::new(),
+        }
+    }
+
+    // a missing file is an empty store, it is created on save
+    pub fn load(path: &Path) -> Result<CalibrationStore, String> {
+        let headsets = if path.exists() {
+            let file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(CalibrationStore {
+            path: Some(path.to_path_buf()),
+            headsets: headsets,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        match self.path {
+            Some(ref path) => {
+                let file = fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                serde_json::to_writer_pretty(file, &self.headsets).map_err(|e| format!("{}: {}", path.display(), e))
+            },
+            None => Err(format!("no calibration file given")),
+        }
+    }
+
+    pub fn lookup(&self, id: &HeadsetId) -> Option<Calibration> {
+        self.headsets.get(id).cloned()
+    }
+
+    pub fn get(&self, id: &HeadsetId, source: &String) -> Calibration {
+        match self.headsets.get(id) {
+            Some(calibration) => *calibration,
+            None => {
+                let (s2a, a2s) = get_levels(source);
+                Calibration { s2a: s2a, a2s: a2s }
+            }
+        }
+    }
+
+    pub fn set(&mut self, id: &HeadsetId, calibration: Calibration) {
+        self.headsets.insert(id.clone(), calibration);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::{Calibration, CalibrationStore};
+
+    #[test]
+    fn test_store_roundtrip() {
+        let path = env::temp_dir().join("egloorator-test-calibration.json");
+        fs::remove_file(&path).ok();
+        let id = format!("H390-1234");
+        let source = format!("pulsesrc device=alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono");
+
+        let mut store = CalibrationStore::load(&path).unwrap();
+        assert_eq!(store.get(&id, &source), Calibration { s2a: -55f64, a2s: -57f64 });
+        store.set(&id, Calibration { s2a: -40f64, a2s: -42f64 });
+        store.save().unwrap();
+
+        let store = CalibrationStore::load(&path).unwrap();
+        assert_eq!(store.get(&id, &source), Calibration { s2a: -40f64, a2s: -42f64 });
+        fs::remove_file(&path).ok();
+    }
+}
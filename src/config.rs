@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use toml;
+
+use calibration::Calibration;
+use headset::HeadsetId;
+use logging::parse_level;
+
+
+// Everything tunable, read from a toml file. Every section and key is optional and
+// defaults to the values egloorator used to have hardcoded. See egloorator.toml.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub devices: DevicesConfig,
+    pub vad: VadConfig,
+    pub matching: MatchingConfig,
+    pub cues: CuesConfig,
+    pub amplification: AmplificationConfig,
+    pub lighting: LightingConfig,
+    pub logging: LoggingConfig,
+    // per model thresholds, first entry whose pattern is in the source name wins
+    pub levels: Vec<LevelsConfig>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DevicesConfig {
+    pub filter_sources: Vec<String>,
+    pub filter_not_sources: Vec<String>,
+    pub all_buses: bool,
+    pub identify_by: String,
+    pub sinks: BTreeMap<String, String>, // source name => sink name
+    pub calibration: Option<String>,
+    pub hotplug_interval: f64,
+    pub record_consent: Vec<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    pub level_interval: f64, // only read at startup, the level pipelines are not rebuilt
+    pub silent_period: i64,  // in level intervals
+    pub average_period: i64, // in level intervals, 1 for no averaging
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MatchingConfig {
+    pub policy: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CuesConfig {
+    pub sine: bool,          // play a sine to a headset that became active
+    pub sine_amplitude: f64,
+    pub sine_duration: u64,  // in level intervals
+    pub sine_while_active: bool, // keep playing for as long as the headset is active (debug)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AmplificationConfig {
+    pub target: f64, // dB, what every speaker is brought to
+    pub headsets: BTreeMap<HeadsetId, f64>, // fixed amplification per headset id
+}
+
+
+// Art-Net output, one rgb + dimmer fixture per seat
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LightingConfig {
+    pub target: String, // host:port of the art-net node, empty for no lighting
+    pub fps: f64,
+    pub silent: [u8; 3],
+    pub single: [u8; 3],     // waiting for someone to talk to
+    pub pairs: Vec<[u8; 3]>, // both seats of a pair get the one of its lower voice
+    pub min_dimmer: u8,      // dimmer of a quiet seat, rms brings it up to 255
+    pub fixtures: Vec<FixtureConfig>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FixtureConfig {
+    pub headset: HeadsetId,
+    #[serde(default)]
+    pub universe: u16,
+    pub channel: u16, // 1 based, red green blue dimmer from here on
+}
+
+
+// read at startup only
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub modules: BTreeMap<String, String>, // module (hub, osc, ...) => level
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LevelsConfig {
+    pub pattern: String,
+    pub s2a: f64,
+    pub a2s: f64,
+}
+
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            devices: DevicesConfig::default(),
+            vad: VadConfig::default(),
+            matching: MatchingConfig::default(),
+            cues: CuesConfig::default(),
+            amplification: AmplificationConfig::default(),
+            lighting: LightingConfig::default(),
+            logging: LoggingConfig::default(),
+            levels: vec![],
+        }
+    }
+}
+
+
+impl Default for DevicesConfig {
+    fn default() -> DevicesConfig {
+        DevicesConfig {
+            filter_sources: vec![],
+            filter_not_sources: vec![],
+            all_buses: false,
+            identify_by: format!("serial"),
+            sinks: BTreeMap::new(),
+            calibration: None,
+            hotplug_interval: 2.0,
+            record_consent: vec![],
+        }
+    }
+}
+
+
+impl Default for VadConfig {
+    fn default() -> VadConfig {
+        VadConfig {
+            level_interval: 0.1,
+            silent_period: 10 * 30,
+            average_period: 1, // no averaging - let level element do that
+        }
+    }
+}
+
+
+impl Default for MatchingConfig {
+    fn default() -> MatchingConfig {
+        MatchingConfig {
+            policy: format!("first-come"),
+        }
+    }
+}
+
+
+impl Default for CuesConfig {
+    fn default() -> CuesConfig {
+        CuesConfig {
+            sine: true,
+            sine_amplitude: 0.02,
+            sine_duration: 5,
+            sine_while_active: false,
+        }
+    }
+}
+
+
+impl Default for AmplificationConfig {
+    fn default() -> AmplificationConfig {
+        AmplificationConfig {
+            target: -30f64,
+            headsets: BTreeMap::new(),
+        }
+    }
+}
+
+
+impl Default for LightingConfig {
+    fn default() -> LightingConfig {
+        LightingConfig {
+            target: format!(""),
+            fps: 30f64,
+            silent: [0, 0, 64],
+            single: [255, 160, 0],
+            pairs: vec![[255, 0, 0], [0, 255, 0], [0, 128, 255], [255, 0, 255], [255, 255, 0], [0, 255, 255]],
+            min_dimmer: 64,
+            fixtures: vec![],
+        }
+    }
+}
+
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: format!("info"),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut text = String::new();
+        fs::File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.matching.policy != "first-come" {
+            return Err(format!("unknown matching policy `{}`", self.matching.policy));
+        }
+        if self.vad.average_period < 1 {
+            return Err(format!("vad.average_period must be at least 1"));
+        }
+        if self.vad.level_interval <= 0.0 {
+            return Err(format!("vad.level_interval must be positive"));
+        }
+        if self.lighting.fps <= 0.0 {
+            return Err(format!("lighting.fps must be positive"));
+        }
+        if self.lighting.pairs.len() == 0 {
+            return Err(format!("lighting.pairs needs at least one colour"));
+        }
+        for fixture in &self.lighting.fixtures {
+            if fixture.channel < 1 || fixture.channel > 509 || fixture.universe > 0x7fff {
+                return Err(format!("fixture of {} is outside of the dmx universe", fixture.headset));
+            }
+        }
+        parse_level(&self.logging.level)?;
+        for level in self.logging.modules.values() {
+            parse_level(level)?;
+        }
+        Ok(())
+    }
+
+    // configured thresholds for a source, if any pattern matches
+    pub fn levels(&self, source: &String) -> Option<Calibration> {
+        self.levels.iter()
+            .find(|l| source.contains(&l.pattern))
+            .map(|l| Calibration { s2a: l.s2a, a2s: l.a2s })
+    }
+
+    pub fn amplification(&self, id: &HeadsetId, calibration: &Calibration) -> f64 {
+        match self.amplification.headsets.get(id) {
+            Some(amplification) => *amplification,
+            None => calibration.amplification(self.amplification.target),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calibration::Calibration;
+    use super::Config;
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("
+[vad]
+silent_period = 20
+
+[cues]
+sine = false
+
+[amplification]
+target = -25.0
+[amplification.headsets]
+\"H390-1234\" = 3.0
+
+[[levels]]
+pattern = \"LX-3000\"
+s2a = -50.0
+a2s = -52.0
+").unwrap();
+        assert_eq!(config.vad.silent_period, 20);
+        assert_eq!(config.vad.average_period, 1);
+        assert!(!config.cues.sine);
+        assert_eq!(config.levels(&format!("pulsesrc device=alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono")),
+                   Some(Calibration { s2a: -50f64, a2s: -52f64 }));
+        assert_eq!(config.levels(&format!("pulsesrc device=other")), None);
+        let c = Calibration { s2a: -40f64, a2s: -42f64 };
+        assert_eq!(config.amplification(&format!("H390-1234"), &c), 3f64);
+        assert_eq!(config.amplification(&format!("other"), &c), 15f64);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Config::parse("[matching]\npolicy = \"random\"").is_err());
+        assert!(Config::parse("[vad]\nsilent_period = \"long\"").is_err());
+        assert!(Config::parse("[[lighting.fixtures]]\nheadset = \"a\"\nchannel = 511").is_err());
+        assert!(Config::parse("[logging.modules]\nhub = \"chatty\"").is_err());
+    }
+}
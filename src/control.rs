@@ -0,0 +1,250 @@
+use std::io::Write;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use serde_json;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use calibration::Calibration;
+use events::{SharedEvents, Stamped};
+use hub::Voice;
+use metrics::SharedMetrics;
+use status::SharedStatus;
+use websocket::{accept_key, text_frame};
+
+
+// Operator overrides, from the control api to the main loop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Connect(Voice, Voice),
+    Disconnect(Voice),
+    Mute(Voice, bool),
+    Thresholds(Voice, Calibration),
+    Offline(Voice, bool),
+    SaveCalibration(Voice), // current thresholds into the calibration file
+}
+
+
+#[derive(Deserialize)]
+struct ConnectBody {
+    voices: (Voice, Voice),
+}
+
+
+#[derive(Deserialize)]
+struct VoiceBody {
+    voice: Voice,
+}
+
+
+#[derive(Deserialize)]
+struct MuteBody {
+    voice: Voice,
+    muted: bool,
+}
+
+
+#[derive(Deserialize)]
+struct ThresholdsBody {
+    voice: Voice,
+    s2a: f64,
+    a2s: f64,
+}
+
+
+#[derive(Deserialize)]
+struct OfflineBody {
+    voice: Voice,
+    offline: bool,
+}
+
+
+fn json<'a, T: ::serde::Deserialize<'a>>(body: &'a str) -> Result<T, String> {
+    serde_json::from_str(body).map_err(|e| format!("bad request body: {}", e))
+}
+
+
+// the operator dashboard, served at /
+const DASHBOARD: &'static str = include_str!("dashboard.html");
+
+
+const COMMANDS: &'static [&'static str] = &["/connect", "/disconnect", "/mute", "/thresholds", "/offline", "/save"];
+
+
+// POST /connect     {"voices": [1, 4]}
+// POST /disconnect  {"voice": 1}
+// POST /mute        {"voice": 1, "muted": true}
+// POST /thresholds  {"voice": 1, "s2a": -50.0, "a2s": -52.0}
+// POST /offline     {"voice": 1, "offline": true}
+// POST /save        {"voice": 1}
+pub fn parse_command(path: &str, body: &str) -> Result<Command, String> {
+    match path {
+        "/connect" => json::<ConnectBody>(body).map(|b| Command::Connect(b.voices.0, b.voices.1)),
+        "/disconnect" => json::<VoiceBody>(body).map(|b| Command::Disconnect(b.voice)),
+        "/mute" => json::<MuteBody>(body).map(|b| Command::Mute(b.voice, b.muted)),
+        "/thresholds" => {
+            let b = json::<ThresholdsBody>(body)?;
+            // the hysteresis needs active above silent
+            if b.s2a < b.a2s {
+                return Err(format!("s2a ({}) must not be below a2s ({})", b.s2a, b.a2s));
+            }
+            Ok(Command::Thresholds(b.voice, Calibration { s2a: b.s2a, a2s: b.a2s }))
+        },
+        "/offline" => json::<OfflineBody>(body).map(|b| Command::Offline(b.voice, b.offline)),
+        "/save" => json::<VoiceBody>(body).map(|b| Command::SaveCalibration(b.voice)),
+        _ => Err(format!("unknown command {}", path)),
+    }
+}
+
+
+fn voices(command: &Command) -> Vec<Voice> {
+    match *command {
+        Command::Connect(one, two) => vec![one, two],
+        Command::Disconnect(voice) | Command::Mute(voice, _) | Command::Thresholds(voice, _)
+            | Command::Offline(voice, _) | Command::SaveCalibration(voice) => vec![voice],
+    }
+}
+
+
+// status code and json body for one request
+pub fn handle(method: &Method, path: &str, body: &str, status: &SharedStatus, commands: &Sender<Command>) -> (u16, String) {
+    match (method, path) {
+        (&Method::Get, "/status") => {
+            let status = status.lock().unwrap();
+            (200, serde_json::to_string_pretty(&*status).unwrap())
+        },
+        (&Method::Post, _) if COMMANDS.contains(&path) => {
+            let command = match parse_command(path, body) {
+                Ok(command) => command,
+                Err(e) => return (400, json!({ "error": e }).to_string()),
+            };
+            let known = {
+                let status = status.lock().unwrap();
+                voices(&command).iter().all(|v| status.voices.contains_key(v))
+            };
+            if !known {
+                return (404, json!({ "error": "unknown voice" }).to_string());
+            }
+            match commands.send(command) {
+                Ok(()) => (202, json!({ "accepted": true }).to_string()),
+                Err(_) => (503, json!({ "error": "shutting down" }).to_string()),
+            }
+        },
+        _ => (404, json!({ "error": format!("no such endpoint {} {}", method, path) }).to_string()),
+    }
+}
+
+
+fn websocket_key(request: &Request) -> Option<String> {
+    request.headers().iter().find(|h| h.field.equiv("Sec-WebSocket-Key")).map(|h| h.value.as_str().to_string())
+}
+
+
+// GET /events: switch to a websocket and push every event as a text message, see
+// events.rs for the schema. Ends when the client goes away.
+fn stream_events(request: Request, key: &str, events: Receiver<Stamped>) {
+    let response = Response::new_empty(StatusCode(101))
+        .with_header("Upgrade: websocket".parse::<Header>().unwrap())
+        .with_header("Connection: Upgrade".parse::<Header>().unwrap())
+        .with_header(format!("Sec-WebSocket-Accept: {}", accept_key(key)).parse::<Header>().unwrap());
+    let mut stream = request.upgrade("websocket", response);
+    thread::spawn(move || {
+        for event in events {
+            let frame = text_frame(&serde_json::to_string(&event).unwrap());
+            if stream.write_all(&frame).and_then(|_| stream.flush()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+
+// Serve the status, the event stream, prometheus metrics at /metrics and accept
+// commands on addr (host:port) from a thread of its own
+pub fn serve(addr: &str, status: SharedStatus, commands: Sender<Command>, events: SharedEvents,
+             metrics: SharedMetrics) -> Result<(), String> {
+    let server = Server::http(addr).map_err(|e| e.to_string())?;
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            if *request.method() == Method::Get && request.url() == "/events" {
+                match websocket_key(&request) {
+                    Some(key) => stream_events(request, &key, events.subscribe()),
+                    None => { request.respond(Response::from_string("websocket only").with_status_code(400)).ok(); },
+                }
+                continue;
+            }
+            if *request.method() == Method::Get && request.url() == "/" {
+                let header = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap();
+                request.respond(Response::from_string(DASHBOARD).with_header(header)).ok();
+                continue;
+            }
+            if *request.method() == Method::Get && request.url() == "/metrics" {
+                let header = Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap();
+                request.respond(Response::from_string(metrics.render()).with_header(header)).ok();
+                continue;
+            }
+            let mut body = String::new();
+            let (code, json) = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => handle(request.method(), request.url(), &body, &status, &commands),
+                Err(e) => (400, json!({ "error": e.to_string() }).to_string()),
+            };
+            let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+            let response = Response::from_string(json).with_status_code(code).with_header(header);
+            request.respond(response).ok();
+        }
+    });
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use tiny_http::Method;
+    use calibration::Calibration;
+    use status::{Status, VoiceStatus};
+    use super::{handle, parse_command, Command, DASHBOARD};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("/connect", "{\"voices\": [1, 4]}"), Ok(Command::Connect(1, 4)));
+        assert_eq!(parse_command("/mute", "{\"voice\": 2, \"muted\": false}"), Ok(Command::Mute(2, false)));
+        assert_eq!(parse_command("/thresholds", "{\"voice\": 3, \"s2a\": -50, \"a2s\": -52}"),
+                   Ok(Command::Thresholds(3, Calibration { s2a: -50f64, a2s: -52f64 })));
+        assert!(parse_command("/thresholds", "{\"voice\": 3, \"s2a\": -55, \"a2s\": -52}").is_err());
+        assert!(parse_command("/disconnect", "{\"who\": 3}").is_err());
+        assert!(parse_command("/reboot", "{}").is_err());
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut status = Status::default();
+        status.voices.insert(1, VoiceStatus::default());
+        status.voices.insert(4, VoiceStatus::default());
+        status.pairs = vec![(1, 4)];
+        let status = Arc::new(Mutex::new(status));
+        let (tx, rx) = channel();
+
+        let (code, body) = handle(&Method::Get, "/status", "", &status, &tx);
+        assert_eq!(code, 200);
+        assert!(body.contains("\"pairs\""));
+        assert_eq!(handle(&Method::Post, "/disconnect", "{\"voice\": 4}", &status, &tx).0, 202);
+        assert_eq!(rx.try_recv(), Ok(Command::Disconnect(4)));
+        assert_eq!(handle(&Method::Post, "/disconnect", "{\"voice\": 7}", &status, &tx).0, 404);
+        assert_eq!(handle(&Method::Post, "/disconnect", "nonsense", &status, &tx).0, 400);
+        assert_eq!(handle(&Method::Post, "/reboot", "{}", &status, &tx).0, 404);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_dashboard_self_contained() {
+        // there is no internet on the playa
+        for external in &["src=", "href=", "<link", "@import", "url("] {
+            assert!(!DASHBOARD.contains(external), "dashboard loads something: {}", external);
+        }
+        for endpoint in &["/status", "/events", "/connect", "/disconnect", "/mute", "/offline", "/thresholds", "/save"] {
+            assert!(DASHBOARD.contains(endpoint), "dashboard does not use {}", endpoint);
+        }
+    }
+}
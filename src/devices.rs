@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+
+// A PulseAudio source or sink, as reported by the server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Device {
+    pub name: String,
+    pub description: String,
+    pub channels: u32,
+    pub sample_rate: u32,
+    // device.* / alsa.* properties, i.e. card, bus, serial, bus path
+    pub properties: BTreeMap<String, String>,
+}
+
+
+impl Device {
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|s| &s[..])
+    }
+
+    pub fn is_monitor(&self) -> bool {
+        self.property("device.class") == Some("monitor")
+    }
+
+    pub fn bus(&self) -> Option<&str> {
+        self.property("device.bus")
+    }
+
+    // the sound card, or the device address of a bluetooth headset, which has none
+    pub fn card(&self) -> Option<&str> {
+        self.property("alsa.card")
+            .or_else(|| self.property("api.bluez5.address"))
+            .or_else(|| self.property("device.string"))
+    }
+}
+
+
+pub trait DeviceProvider {
+    fn sources(&self) -> Result<Vec<Device>, String>;
+    fn sinks(&self) -> Result<Vec<Device>, String>;
+}
+
+
+// Queries the PulseAudio server via pactl. The long listing is parsed with LC_ALL=C so
+// field names do not depend on the locale; properties are locale independent anyway.
+pub struct PulseAudio;
+
+
+impl PulseAudio {
+    fn list(&self, what: &str) -> Result<Vec<Device>, String> {
+        let output = Command::new("pactl")
+            .env("LC_ALL", "C")
+            .arg("list")
+            .arg(what)
+            .output()
+            .map_err(|e| format!("cannot run pactl: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("pactl list {} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(parse_pactl_list(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+
+impl DeviceProvider for PulseAudio {
+    fn sources(&self) -> Result<Vec<Device>, String> {
+        self.list("sources")
+    }
+
+    fn sinks(&self) -> Result<Vec<Device>, String> {
+        self.list("sinks")
+    }
+}
+
+
+// A fixed list of devices, for tests and machines without a sound server
+pub struct StaticDevices {
+    pub sources: Vec<Device>,
+    pub sinks: Vec<Device>,
+}
+
+
+impl DeviceProvider for StaticDevices {
+    fn sources(&self) -> Result<Vec<Device>, String> {
+        Ok(self.sources.clone())
+    }
+
+    fn sinks(&self) -> Result<Vec<Device>, String> {
+        Ok(self.sinks.clone())
+    }
+}
+
+
+// a device with just the given properties, for the tests of the modules that match them
+#[cfg(test)]
+pub fn device(name: &str, properties: &[(&str, &str)]) -> Device {
+    Device {
+        name: String::from(name),
+        description: String::from(name),
+        channels: 1,
+        sample_rate: 44100,
+        properties: properties.iter().map(|&(k, v)| (String::from(k), String::from(v))).collect(),
+    }
+}
+
+
+fn new_device() -> Device {
+    Device {
+        name: String::new(),
+        description: String::new(),
+        channels: 0,
+        sample_rate: 0,
+        properties: BTreeMap::new(),
+    }
+}
+
+
+// "s16le 2ch 44100Hz" => (2, 44100)
+fn parse_sample_spec(spec: &str) -> (u32, u32) {
+    let mut channels = 0;
+    let mut rate = 0;
+    for part in spec.split_whitespace() {
+        if part.ends_with("ch") {
+            channels = part.trim_end_matches("ch").parse().unwrap_or(0);
+        } else if part.ends_with("Hz") {
+            rate = part.trim_end_matches("Hz").parse().unwrap_or(0);
+        }
+    }
+    (channels, rate)
+}
+
+
+// parse the output of `pactl list sources` or `pactl list sinks`
+pub fn parse_pactl_list(text: &str) -> Vec<Device> {
+    let mut out = Vec::new();
+    let mut current: Option<Device> = None;
+    let mut in_properties = false;
+
+    for line in text.lines() {
+        if line.starts_with("Source #") || line.starts_with("Sink #") {
+            if let Some(device) = current.take() {
+                out.push(device);
+            }
+            current = Some(new_device());
+            in_properties = false;
+            continue;
+        }
+        let device = match current {
+            Some(ref mut device) => device,
+            None => continue,
+        };
+        if line.starts_with("\t\t") {
+            if !in_properties {
+                continue;
+            }
+            let mut kv = line.trim().splitn(2, " = ");
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => {
+                    device.properties.insert(String::from(key), String::from(value.trim_matches('"')));
+                },
+                _ => {},
+            }
+        } else if line.starts_with("\t") {
+            let mut kv = line.trim().splitn(2, ":");
+            let key = kv.next().unwrap_or("");
+            let value = kv.next().unwrap_or("").trim();
+            in_properties = key == "Properties";
+            match key {
+                "Name" => device.name = String::from(value),
+                "Description" => device.description = String::from(value),
+                "Sample Specification" => {
+                    let (channels, rate) = parse_sample_spec(value);
+                    device.channels = channels;
+                    device.sample_rate = rate;
+                },
+                _ => {},
+            }
+        }
+    }
+    if let Some(device) = current.take() {
+        out.push(device);
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pactl_list;
+
+    const LX3000: &'static str = "Source #0
+\tState: SUSPENDED
+\tName: alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo.monitor
+\tDescription: Monitor of Microsoft LifeChat LX-3000 Analog Stereo
+\tSample Specification: s16le 2ch 44100Hz
+\tProperties:
+\t\tdevice.description = \"Monitor of Microsoft LifeChat LX-3000 Analog Stereo\"
+\t\tdevice.class = \"monitor\"
+\t\talsa.card = \"1\"
+Source #1
+\tState: SUSPENDED
+\tName: alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono
+\tDescription: Microsoft LifeChat LX-3000 Analog Mono
+\tSample Specification: s16le 1ch 44100Hz
+\tChannel Map: mono
+\tVolume: mono: 65536 / 100% / 0.00 dB
+\t        balance 0.00
+\tProperties:
+\t\talsa.card = \"1\"
+\t\tdevice.bus_path = \"pci-0000:00:14.0-usb-0:2:1.0\"
+\t\tdevice.bus = \"usb\"
+\t\tdevice.class = \"sound\"
+\t\tdevice.serial = \"C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000\"
+\tFormats:
+\t\tpcm
+";
+
+    #[test]
+    fn test_parse_pactl_list() {
+        let devices = parse_pactl_list(LX3000);
+        assert_eq!(devices.len(), 2);
+        assert!(devices[0].is_monitor());
+        assert_eq!(devices[0].channels, 2);
+        let d = &devices[1];
+        assert!(!d.is_monitor());
+        assert_eq!(d.name, "alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono");
+        assert_eq!(d.description, "Microsoft LifeChat LX-3000 Analog Mono");
+        assert_eq!((d.channels, d.sample_rate), (1, 44100));
+        assert_eq!(d.bus(), Some("usb"));
+        assert_eq!(d.card(), Some("1"));
+        assert_eq!(d.property("device.bus_path"), Some("pci-0000:00:14.0-usb-0:2:1.0"));
+        assert_eq!(d.property("pcm"), None);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use serde_json;
+
+use events::{Event, Stamped};
+use hub::Voice;
+
+
+// The whole night as json lines, one record per line, for looking back at it later:
+//
+//   {"time": "2018-08-27T21:03:11.250+02:00", "event": "active", "voice": 3, "headset": "H390-1234",
+//    "rms": -41.5, "s2a": -45.0, "a2s": -47.0}
+//   {"time": ..., "event": "connect", "voice": 3, "headset": "H390-1234", "partner": 1, "partner_headset": "LX-3000"}
+//   {"time": ..., "event": "error", "voice": 3, "headset": "H390-1234", "error": "..."}
+//   {"time": ..., "event": "restart", "voice": 3, "headset": "H390-1234", "restarts": 2}
+//
+// event is one of active, silent, connect, disconnect, error and restart. rms is the
+// level at the transition, s2a and a2s the thresholds in effect at the time. Level
+// snapshots are left out. When the file grows beyond its size it is renamed to
+// path.1 (path.1 to path.2 and so on) and a new one started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub time: String,
+    pub event: String,
+    pub voice: Voice,
+    pub headset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s2a: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub a2s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partner: Option<Voice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partner_headset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restarts: Option<u32>,
+}
+
+
+impl Record {
+    fn new(time: &String, event: &str, voice: Voice, headset: &String) -> Record {
+        Record {
+            time: time.clone(),
+            event: String::from(event),
+            voice: voice,
+            headset: headset.clone(),
+            rms: None,
+            s2a: None,
+            a2s: None,
+            partner: None,
+            partner_headset: None,
+            error: None,
+            restarts: None,
+        }
+    }
+}
+
+
+pub fn record(stamped: &Stamped) -> Option<Record> {
+    let time = &stamped.time;
+    match stamped.event {
+        Event::SilenceChange { voice, ref id, silent, rms, s2a, a2s } => Some(Record {
+            rms: Some(rms),
+            s2a: Some(s2a),
+            a2s: Some(a2s),
+            ..Record::new(time, if silent { "silent" } else { "active" }, voice, id)
+        }),
+        Event::Connect { voices, ref ids } | Event::Disconnect { voices, ref ids } => {
+            let event = match stamped.event {
+                Event::Connect { .. } => "connect",
+                _ => "disconnect",
+            };
+            Some(Record {
+                partner: Some(voices.1),
+                partner_headset: Some(ids.1.clone()),
+                ..Record::new(time, event, voices.0, &ids.0)
+            })
+        },
+        Event::Error { voice, ref id, ref error } => Some(Record {
+            error: Some(error.clone()),
+            ..Record::new(time, "error", voice, id)
+        }),
+        Event::Restart { voice, ref id, restarts } => Some(Record {
+            restarts: Some(restarts),
+            ..Record::new(time, "restart", voice, id)
+        }),
+        Event::Levels { .. } => None,
+    }
+}
+
+
+pub struct EventLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+
+fn open(path: &Path) -> Result<File, String> {
+    OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+
+impl EventLog {
+    // appends to an existing log, keep is how many rotated files to hold on to
+    pub fn new(path: &Path, max_bytes: u64, keep: usize) -> Result<EventLog, String> {
+        let file = open(path)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(EventLog {
+            path: path.to_path_buf(),
+            max_bytes: max_bytes,
+            keep: keep,
+            file: file,
+            written: written,
+        })
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path).ok();
+        } else {
+            fs::remove_file(rotated(&self.path, self.keep)).ok();
+            for n in (1..self.keep).rev() {
+                fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1)).ok();
+            }
+            fs::rename(&self.path, rotated(&self.path, 1)).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        }
+        self.file = open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), String> {
+        let mut line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        line.push('\n');
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes()).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+
+// Write every event to the log from a thread of its own
+pub fn run(mut log: EventLog, events: Receiver<Stamped>) {
+    thread::spawn(move || {
+        for stamped in events {
+            match record(&stamped) {
+                Some(record) => match log.write(&record) {
+                    Ok(_) => {},
+                    Err(e) => error!("event log: {}", e),
+                },
+                None => {}
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use serde_json;
+    use events::{Event, Stamped};
+    use super::{record, rotated, EventLog, Record};
+
+    fn stamped(event: Event) -> Stamped {
+        Stamped { time: format!("t"), event: event }
+    }
+
+    #[test]
+    fn test_record() {
+        let r = record(&stamped(Event::SilenceChange { voice: 3, id: format!("a"), silent: false, rms: -41.5, s2a: -45.0, a2s: -47.0 })).unwrap();
+        assert_eq!(serde_json::to_value(&r).unwrap(), json!({
+            "time": "t", "event": "active", "voice": 3, "headset": "a", "rms": -41.5, "s2a": -45.0, "a2s": -47.0
+        }));
+        let r = record(&stamped(Event::Disconnect { voices: (3, 1), ids: (format!("a"), format!("b")) })).unwrap();
+        assert_eq!(serde_json::to_value(&r).unwrap(), json!({
+            "time": "t", "event": "disconnect", "voice": 3, "headset": "a", "partner": 1, "partner_headset": "b"
+        }));
+        let r = record(&stamped(Event::Restart { voice: 2, id: format!("c"), restarts: 4 })).unwrap();
+        assert_eq!((&r.event[..], r.restarts), ("restart", Some(4)));
+        assert_eq!(serde_json::from_str::<Record>(&serde_json::to_string(&r).unwrap()).unwrap(), r);
+        assert_eq!(record(&stamped(Event::Levels { voices: BTreeMap::new() })), None);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = env::temp_dir().join("egloorator-test-eventlog");
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let r = record(&stamped(Event::Error { voice: 1, id: format!("a"), error: format!("gone") })).unwrap();
+        let line = serde_json::to_string(&r).unwrap().len() as u64 + 1;
+        // two lines per file, two rotated files
+        let mut log = EventLog::new(&path, line * 2, 2).unwrap();
+        for _ in 0..7 {
+            log.write(&r).unwrap();
+        }
+        let lines = |p: &Path| fs::read_to_string(p).unwrap().lines().count();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&rotated(&path, 1)), 2);
+        assert_eq!(lines(&rotated(&path, 2)), 2);
+        assert!(!rotated(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use chrono::Local;
+
+use hub::Voice;
+
+
+// Things worth telling the outside world about, as they happen. Every event is
+// serialized as one json object with the local time and a type tag:
+//
+//   {"time": "2018-08-27T21:03:11.250+02:00", "type": "silence_change", "voice": 3, "id": "H390-1234", "silent": false,
+//    "rms": -41.5, "s2a": -45.0, "a2s": -47.0}
+//   {"time": ..., "type": "connect", "voices": [3, 1], "ids": ["H390-1234", "LX-3000"]}
+//   {"time": ..., "type": "disconnect", "voices": [3, 1], "ids": ["H390-1234", "LX-3000"]}
+//   {"time": ..., "type": "error", "voice": 3, "id": "H390-1234", "error": "error from element `pulsesrc0`: ..."}
+//   {"time": ..., "type": "restart", "voice": 3, "id": "H390-1234", "restarts": 2}
+//   {"time": ..., "type": "levels", "voices": {"1": {"rms": -48.5, "silent": true}, "3": {"rms": -31.2, "silent": false}}}
+//
+// time is rfc 3339, rms and thresholds are in dB, rms being the level that caused the
+// change. voices are the indices used by the control api, ids the stable headset ids.
+// error is a level pipeline or conversation failing, restart a level pipeline running
+// again after restarts. levels is a periodic snapshot of every voice that reported a
+// level so far.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    SilenceChange { voice: Voice, id: String, silent: bool, rms: f64, s2a: f64, a2s: f64 },
+    Connect { voices: (Voice, Voice), ids: (String, String) },
+    Disconnect { voices: (Voice, Voice), ids: (String, String) },
+    Error { voice: Voice, id: String, error: String },
+    Restart { voice: Voice, id: String, restarts: u32 },
+    Levels { voices: BTreeMap<Voice, Level> },
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Level {
+    pub rms: f64,
+    pub silent: bool,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stamped {
+    pub time: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+
+// Hands every published event to everyone subscribed. A subscriber whose receiver is
+// gone is dropped on the next publish.
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<Stamped>>>,
+}
+
+
+pub type SharedEvents = Arc<Events>;
+
+
+impl Events {
+    pub fn new() -> SharedEvents {
+        Arc::new(Events {
+            subscribers: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn subscribe(&self) -> Receiver<Stamped> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish(&self, event: Event) {
+        let stamped = Stamped {
+            time: Local::now().to_rfc3339(),
+            event: event,
+        };
+        self.subscribers.lock().unwrap().retain(|s| s.send(stamped.clone()).is_ok());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use serde_json;
+    use super::{Event, Events, Level, Stamped};
+
+    fn change(silent: bool) -> Event {
+        Event::SilenceChange { voice: 2, id: format!("a"), silent: silent, rms: -40.0, s2a: -45.0, a2s: -47.0 }
+    }
+
+    #[test]
+    fn test_schema() {
+        let stamped = Stamped {
+            time: format!("2018-08-27T21:03:11.250+02:00"),
+            event: Event::Connect { voices: (3, 1), ids: (format!("H390-1234"), format!("LX-3000")) },
+        };
+        assert_eq!(serde_json::to_value(&stamped).unwrap(), json!({
+            "time": "2018-08-27T21:03:11.250+02:00", "type": "connect", "voices": [3, 1], "ids": ["H390-1234", "LX-3000"]
+        }));
+        let stamped = Stamped { time: format!("t"), event: change(false) };
+        assert_eq!(serde_json::to_value(&stamped).unwrap(), json!({
+            "time": "t", "type": "silence_change", "voice": 2, "id": "a", "silent": false, "rms": -40.0, "s2a": -45.0, "a2s": -47.0
+        }));
+        let mut voices = BTreeMap::new();
+        voices.insert(1, Level { rms: -48.5, silent: true });
+        let stamped = Stamped { time: format!("t"), event: Event::Levels { voices: voices } };
+        assert_eq!(serde_json::to_value(&stamped).unwrap(), json!({
+            "time": "t", "type": "levels", "voices": {"1": {"rms": -48.5, "silent": true}}
+        }));
+    }
+
+    #[test]
+    fn test_subscribers() {
+        let events = Events::new();
+        let first = events.subscribe();
+        let second = events.subscribe();
+        events.publish(change(false));
+        assert_eq!(first.try_recv().unwrap().event, change(false));
+        assert_eq!(second.try_recv().unwrap().event, change(false));
+        drop(first);
+        events.publish(change(true));
+        assert_eq!(second.try_recv().unwrap().event, change(true));
+        assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+    }
+}
@@ -2,6 +2,11 @@ extern crate gobject_sys;
 extern crate gst;
 
 use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::ElementT;
 
 use gobject_sys::{g_value_get_boxed, g_value_array_get_nth, g_value_get_double}; // TODO: use wrappers provided by gtk-rs and friends
 
@@ -9,6 +14,9 @@ use gobject_sys::{g_value_get_boxed, g_value_array_get_nth, g_value_get_double};
 // Helpers that should go into gstreamer1.0-rs
 
 
+static FINALIZING: AtomicUsize = AtomicUsize::new(0);
+
+
 pub fn gst_structure_get_double(st: &gst::ffi::GstStructure, name: &str) -> f64 {
     unsafe {
         let gst_array_val = gst::ffi::gst_structure_get_value(st, CString::new(name).unwrap().as_ptr());
@@ -41,3 +49,46 @@ pub fn gst_message_get_double(message: &gst::Message, key: &str) -> f64
         gst_structure_get_double(&*st, key)
     }
 }
+
+
+// Send EOS so muxers and wavenc write their headers, wait up to a second for it to
+// reach the sink, then stop the pipeline.
+pub fn gst_finalize_pipeline(pipe: &mut gst::Pipeline)
+{
+    let receiver = pipe.bus().expect("Couldn't get bus from pipeline").receiver();
+    pipe.send_event(gst::Event::new_eos());
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(message) => match message.parse() {
+                gst::Message::Eos(_) | gst::Message::ErrorParsed{..} => break,
+                _ => {},
+            },
+            Err(_) => {},
+        }
+    }
+    pipe.set_null_state();
+}
+
+
+// gst_finalize_pipeline on a thread of its own so the caller, i.e. the hub hanging up a
+// conversation, does not wait for the EOS. then runs once the file is complete.
+pub fn gst_finalize_pipeline_later<F: FnOnce() + Send + 'static>(pipe: gst::Pipeline, then: F)
+{
+    FINALIZING.fetch_add(1, Ordering::SeqCst);
+    thread::spawn(move || {
+        let mut pipe = pipe;
+        gst_finalize_pipeline(&mut pipe);
+        then();
+        FINALIZING.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+
+// before exiting, for the files of the conversations just hung up
+pub fn gst_wait_finalized()
+{
+    while FINALIZING.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
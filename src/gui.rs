@@ -0,0 +1,328 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+use gdk;
+use glib;
+use gtk;
+use gtk::prelude::*;
+
+use calibration::Calibration;
+use control::Command;
+use hub::Voice;
+use status::{SharedStatus, Status};
+
+
+// range of the level meters and threshold sliders, dB
+const MIN_DB: f64 = -90f64;
+const MAX_DB: f64 = 0f64;
+
+
+fn fraction(db: f64) -> f64 {
+    ((db - MIN_DB) / (MAX_DB - MIN_DB)).max(0f64).min(1f64)
+}
+
+
+// the threshold x pixels into a meter width pixels wide, on the sliders' 0.5 dB steps
+fn db_at(x: f64, width: f64) -> f64 {
+    let db = MIN_DB + (x / width).max(0f64).min(1f64) * (MAX_DB - MIN_DB);
+    (db * 2f64).round() / 2f64
+}
+
+
+fn paired(status: &Status, one: Voice, two: Voice) -> bool {
+    status.pairs.contains(&(one, two)) || status.pairs.contains(&(two, one))
+}
+
+
+// One headset: its meter with the s2a (red) and a2s (blue) thresholds drawn across it,
+// and the same thresholds as sliders right below. Dragging a line moves its slider, so
+// either way the thresholds are set against the live level.
+struct Row {
+    meter: gtk::DrawingArea,
+    level: Rc<Cell<f64>>, // fraction of the meter lit
+    s2a: gtk::Scale,
+    a2s: gtk::Scale,
+    state: gtk::Label,
+    shown: Calibration, // last thresholds from the status, to not fight the operator
+}
+
+
+struct Gui {
+    levels: gtk::Grid,
+    matrix: gtk::Grid,
+    message: gtk::Label,
+    rows: BTreeMap<Voice, Row>,
+    cells: BTreeMap<(Voice, Voice), gtk::Button>,
+}
+
+
+fn slider() -> gtk::Scale {
+    let scale = gtk::Scale::new_with_range(gtk::Orientation::Horizontal, MIN_DB, MAX_DB, 0.5);
+    scale.set_size_request(300, -1);
+    scale
+}
+
+
+fn meter(level: &Rc<Cell<f64>>, s2a: &gtk::Scale, a2s: &gtk::Scale) -> gtk::DrawingArea {
+    let area = gtk::DrawingArea::new();
+    area.set_size_request(300, 20);
+    area.add_events((gdk::EventMask::BUTTON_PRESS_MASK | gdk::EventMask::BUTTON1_MOTION_MASK).bits() as i32);
+
+    let (draw_level, draw_s2a, draw_a2s) = (level.clone(), s2a.clone(), a2s.clone());
+    area.connect_draw(move |area, cr| {
+        let width = area.get_allocated_width() as f64;
+        let height = area.get_allocated_height() as f64;
+        cr.set_source_rgb(0.2, 0.2, 0.2);
+        cr.rectangle(0f64, 0f64, width, height);
+        cr.fill();
+        cr.set_source_rgb(0.3, 0.8, 0.3);
+        cr.rectangle(0f64, 0f64, width * draw_level.get(), height);
+        cr.fill();
+        cr.set_line_width(2f64);
+        for &(scale, r, b) in &[(&draw_s2a, 1f64, 0f64), (&draw_a2s, 0f64, 1f64)] {
+            let x = width * fraction(scale.get_value());
+            cr.set_source_rgb(r, 0.2, b);
+            cr.move_to(x, 0f64);
+            cr.line_to(x, height);
+            cr.stroke();
+        }
+        gtk::Inhibit(false)
+    });
+
+    // a press picks the nearer line, moving the mouse with the button held drags it;
+    // its slider sends the thresholds
+    let dragging_s2a = Rc::new(Cell::new(true));
+    let (press_s2a, press_a2s, pressed) = (s2a.clone(), a2s.clone(), dragging_s2a.clone());
+    area.connect_button_press_event(move |area, event| {
+        let db = db_at(event.get_position().0, area.get_allocated_width() as f64);
+        let s2a_nearer = (db - press_s2a.get_value()).abs() <= (db - press_a2s.get_value()).abs();
+        pressed.set(s2a_nearer);
+        if s2a_nearer { press_s2a.set_value(db) } else { press_a2s.set_value(db) }
+        gtk::Inhibit(true)
+    });
+    let (drag_s2a, drag_a2s) = (s2a.clone(), a2s.clone());
+    area.connect_motion_notify_event(move |area, event| {
+        let db = db_at(event.get_position().0, area.get_allocated_width() as f64);
+        if dragging_s2a.get() { drag_s2a.set_value(db) } else { drag_a2s.set_value(db) }
+        gtk::Inhibit(true)
+    });
+    area
+}
+
+
+fn remove_children(grid: &gtk::Grid) {
+    for child in grid.get_children() {
+        grid.remove(&child);
+    }
+}
+
+
+fn send_thresholds(gui: &Rc<RefCell<Gui>>, commands: &Sender<Command>, voice: Voice) {
+    // borrowed while the sliders are moved from the status rather than by hand
+    let gui = match gui.try_borrow() {
+        Ok(gui) => gui,
+        Err(_) => return,
+    };
+    let row = &gui.rows[&voice];
+    let calibration = Calibration { s2a: row.s2a.get_value(), a2s: row.a2s.get_value() };
+    if calibration.s2a < calibration.a2s {
+        gui.message.set_text(&format!("voice {}: s2a must not be below a2s", voice));
+        return;
+    }
+    gui.message.set_text("");
+    commands.send(Command::Thresholds(voice, calibration)).ok();
+}
+
+
+// a fresh set of rows and matrix cells whenever headsets come or go
+fn rebuild(gui_rc: &Rc<RefCell<Gui>>, status: &Status, commands: &Sender<Command>) {
+    let mut gui = gui_rc.borrow_mut();
+    remove_children(&gui.levels);
+    remove_children(&gui.matrix);
+    gui.rows.clear();
+    gui.cells.clear();
+
+    for (i, (voice, v)) in status.voices.iter().enumerate() {
+        let voice = *voice;
+        let top = i as i32;
+        let name = gtk::Label::new(Some(format!("{}: {}", voice, v.id).as_str()));
+        let s2a = slider();
+        let a2s = slider();
+        let level = Rc::new(Cell::new(0f64));
+        let meter = meter(&level, &s2a, &a2s);
+        let state = gtk::Label::new(Some(""));
+        let save = gtk::Button::new_with_label("save");
+        let column = gtk::Box::new(gtk::Orientation::Vertical, 2);
+        column.pack_start(&meter, false, false, 0);
+        column.pack_start(&s2a, false, false, 0);
+        column.pack_start(&a2s, false, false, 0);
+        gui.levels.attach(&name, 0, top, 1, 1);
+        gui.levels.attach(&column, 1, top, 1, 1);
+        gui.levels.attach(&state, 2, top, 1, 1);
+        gui.levels.attach(&save, 3, top, 1, 1);
+
+        for scale in &[&s2a, &a2s] {
+            let gui = gui_rc.clone();
+            let commands = commands.clone();
+            let meter = meter.clone();
+            scale.connect_value_changed(move |_| {
+                meter.queue_draw();
+                send_thresholds(&gui, &commands, voice);
+            });
+        }
+        let save_commands = commands.clone();
+        save.connect_clicked(move |_| { save_commands.send(Command::SaveCalibration(voice)).ok(); });
+
+        s2a.set_value(v.s2a);
+        a2s.set_value(v.a2s);
+        gui.rows.insert(voice, Row {
+            meter: meter,
+            level: level,
+            s2a: s2a,
+            a2s: a2s,
+            state: state,
+            shown: Calibration { s2a: v.s2a, a2s: v.a2s },
+        });
+    }
+
+    // who talks to whom, press a cell to force that pair
+    let voices = status.voices.keys().cloned().collect::<Vec<Voice>>();
+    for (i, voice) in voices.iter().enumerate() {
+        gui.matrix.attach(&gtk::Label::new(Some(format!("{}", voice).as_str())), 0, i as i32 + 1, 1, 1);
+        gui.matrix.attach(&gtk::Label::new(Some(format!("{}", voice).as_str())), i as i32 + 1, 0, 1, 1);
+    }
+    for (i, one) in voices.iter().enumerate() {
+        for (j, two) in voices.iter().enumerate() {
+            if one == two {
+                continue;
+            }
+            let (one, two) = (*one, *two);
+            let cell = gtk::Button::new_with_label("");
+            let cell_commands = commands.clone();
+            cell.connect_clicked(move |_| { cell_commands.send(Command::Connect(one, two)).ok(); });
+            gui.matrix.attach(&cell, j as i32 + 1, i as i32 + 1, 1, 1);
+            gui.cells.insert((one, two), cell);
+        }
+    }
+    gui.levels.show_all();
+    gui.matrix.show_all();
+}
+
+
+fn update(gui_rc: &Rc<RefCell<Gui>>, status: &Status) {
+    let mut gui = gui_rc.borrow_mut();
+    for (voice, row) in gui.rows.iter_mut() {
+        let v = match status.voices.get(voice) {
+            Some(v) => v,
+            None => continue,
+        };
+        row.level.set(v.rms.map(fraction).unwrap_or(0f64));
+        row.meter.queue_draw();
+        // only follow the status when the thresholds changed elsewhere (reload, dashboard)
+        let calibration = Calibration { s2a: v.s2a, a2s: v.a2s };
+        if calibration != row.shown {
+            row.s2a.set_value(v.s2a);
+            row.a2s.set_value(v.a2s);
+            row.shown = calibration;
+        }
+        let state = if v.offline {
+            "offline"
+        } else if v.faulted {
+            "faulted"
+        } else if v.muted {
+            "muted"
+        } else if status.single == Some(*voice) {
+            "waiting"
+        } else if status.pairs.iter().any(|&(a, b)| a == *voice || b == *voice) {
+            "talking"
+        } else if v.silent {
+            "silent"
+        } else {
+            "active"
+        };
+        row.state.set_text(state);
+    }
+    for (&(one, two), cell) in gui.cells.iter() {
+        cell.set_label(if paired(status, one, two) { "●" } else { "" });
+    }
+}
+
+
+// The operator window, runs gtk on the calling thread until the window is closed or
+// running turns false. Replaces the python calibration tool.
+pub fn run(status: SharedStatus, commands: Sender<Command>, running: Arc<AtomicBool>) -> Result<(), String> {
+    gtk::init().map_err(|_| format!("cannot initialize gtk"))?;
+
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    window.set_title("Egloorator");
+    window.set_border_width(10);
+    window.connect_delete_event(|_, _| {
+        gtk::main_quit();
+        gtk::Inhibit(false)
+    });
+
+    let layout = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    let gui = Rc::new(RefCell::new(Gui {
+        levels: gtk::Grid::new(),
+        matrix: gtk::Grid::new(),
+        message: gtk::Label::new(Some("")),
+        rows: BTreeMap::new(),
+        cells: BTreeMap::new(),
+    }));
+    {
+        let g = gui.borrow();
+        g.levels.set_column_spacing(10);
+        layout.pack_start(&g.levels, false, false, 0);
+        layout.pack_start(&gtk::Label::new(Some("pairs")), false, false, 0);
+        layout.pack_start(&g.matrix, false, false, 0);
+        layout.pack_start(&g.message, false, false, 0);
+    }
+    window.add(&layout);
+    window.show_all();
+
+    let mut shown_voices: Vec<Voice> = vec![];
+    gtk::timeout_add(100, move || {
+        if !running.load(Ordering::SeqCst) {
+            gtk::main_quit();
+            return glib::Continue(false);
+        }
+        let status = status.lock().unwrap().clone();
+        let voices = status.voices.keys().cloned().collect::<Vec<Voice>>();
+        if voices != shown_voices {
+            rebuild(&gui, &status, &commands);
+            shown_voices = voices;
+        }
+        update(&gui, &status);
+        glib::Continue(true)
+    });
+
+    gtk::main();
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use status::Status;
+    use super::{db_at, fraction, paired};
+
+    #[test]
+    fn test_meter() {
+        assert_eq!(fraction(-90f64), 0f64);
+        assert_eq!(fraction(-45f64), 0.5f64);
+        assert_eq!(fraction(-120f64), 0f64);
+        assert_eq!(fraction(3f64), 1f64);
+        assert_eq!(db_at(150f64, 300f64), -45f64);
+        assert_eq!(db_at(151f64, 300f64), -44.5f64);
+        assert_eq!(db_at(-5f64, 300f64), -90f64);
+        assert_eq!(db_at(310f64, 300f64), 0f64);
+        let mut status = Status::default();
+        status.pairs = vec![(1, 4)];
+        assert!(paired(&status, 4, 1));
+        assert!(!paired(&status, 1, 2));
+    }
+}
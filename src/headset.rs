@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use calibration::{Calibration, CalibrationStore};
+use config::Config;
+use devices::{Device, DeviceProvider};
+use levels::get_levels;
+use sinks::match_sinks;
+use sources::{get_sources, SourceFilter};
+
+
+// Stable name of a headset, survives replugs and reboots, unlike the voice index
+pub type HeadsetId = String;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdentifyBy {
+    Serial, // settings follow the physical headset
+    Port,   // settings follow the usb port, i.e. the seat
+}
+
+
+impl IdentifyBy {
+    pub fn parse(s: &str) -> Option<IdentifyBy> {
+        match s {
+            "serial" => Some(IdentifyBy::Serial),
+            "port" => Some(IdentifyBy::Port),
+            _ => None,
+        }
+    }
+}
+
+
+// Everything the hub and the level watchers need to know about one headset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Headset {
+    pub id: HeadsetId,
+    pub source: String, // gstreamer source pipeline fragment
+    pub sink: String,   // gstreamer sink pipeline fragment
+    pub calibration: Calibration,
+    pub amplification: f64, // dB, applied when this headset is heard by another
+    pub consent: bool,  // wearer agreed to conversation recording
+}
+
+
+fn headset_id(device: &Device, by: IdentifyBy) -> HeadsetId {
+    let key = match by {
+        IdentifyBy::Serial => "device.serial",
+        IdentifyBy::Port => "device.bus_path",
+    };
+    String::from(device.property(key).unwrap_or(&device.name))
+}
+
+
+// Identical headsets share a serial, those get the port path appended to stay unique.
+pub fn headset_ids(devices: &Vec<Device>, by: IdentifyBy) -> Vec<HeadsetId>
+{
+    let ids = devices.iter().map(|d| headset_id(d, by)).collect::<Vec<HeadsetId>>();
+    ids.iter().zip(devices).map(|(id, device)| {
+        if ids.iter().filter(|other| *other == id).count() > 1 {
+            format!("{}@{}", id, device.property("device.bus_path").unwrap_or(&device.name))
+        } else {
+            id.clone()
+        }
+    }).collect()
+}
+
+
+// Turns the devices currently present into headsets, the same way at startup and on
+// every hotplug poll.
+pub struct Discovery {
+    pub filter: SourceFilter,
+    pub sink_overrides: Vec<(String, String)>,
+    pub identify_by: IdentifyBy,
+    pub calibration: CalibrationStore,
+    pub record_consent: Vec<String>, // substrings of headset id or source
+    pub config: Config,
+}
+
+
+impl Discovery {
+    // returns the headsets and warnings about sources and sinks that could not be matched
+    pub fn headsets(&self, provider: &DeviceProvider) -> Result<(Vec<Headset>, Vec<String>), String>
+    {
+        let source_devices = get_sources(provider, &self.filter)?;
+        let sink_devices = provider.sinks()?;
+        let sink_match = match_sinks(&source_devices, &sink_devices, &self.sink_overrides);
+        let mut warnings = Vec::new();
+        for sink in &sink_match.unmatched_sinks {
+            warnings.push(format!("sink {} has no matching source", sink));
+        }
+        for sink in &sink_match.unknown_sinks {
+            warnings.push(format!("sink {} given with --sink or [devices.sinks] does not exist", sink));
+        }
+        let ids = headset_ids(&source_devices, self.identify_by);
+        let mut headsets = Vec::new();
+        for ((source, sink), id) in source_devices.iter().zip(sink_match.sinks).zip(ids) {
+            match sink {
+                Some(sink) => {
+                    let source = format!("pulsesrc device={}", source.name);
+                    headsets.push(self.headset(id, source, format!("pulsesink device={}", sink)));
+                },
+                None => warnings.push(format!("source {} has no matching sink, not using it (use --sink to set one)", source.name)),
+            }
+        }
+        Ok((headsets, warnings))
+    }
+
+    // thresholds come from the calibration store, then the config, then the builtin table
+    fn calibration(&self, id: &HeadsetId, source: &String) -> Calibration
+    {
+        match self.calibration.lookup(id).or_else(|| self.config.levels(source)) {
+            Some(calibration) => calibration,
+            None => {
+                let (s2a, a2s) = get_levels(source);
+                Calibration { s2a: s2a, a2s: a2s }
+            }
+        }
+    }
+
+    pub fn headset(&self, id: HeadsetId, source: String, sink: String) -> Headset
+    {
+        let calibration = self.calibration(&id, &source);
+        Headset {
+            amplification: self.config.amplification(&id, &calibration),
+            calibration: calibration,
+            consent: self.record_consent.iter().any(|c| id.contains(c) || source.contains(c)),
+            id: id,
+            source: source,
+            sink: sink,
+        }
+    }
+}
+
+
+// file based sources are identified by their base name
+pub fn file_headset_id(filename: &String) -> HeadsetId
+{
+    Path::new(filename).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or(filename.clone())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use devices::device;
+    use super::{headset_ids, IdentifyBy};
+
+    #[test]
+    fn test_headset_ids() {
+        let devices = vec![
+            device("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono", &[("device.serial", "LX-3000"), ("device.bus_path", "pci-0000:00:14.0-usb-0:1:1.0")]),
+            device("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono.2", &[("device.serial", "LX-3000"), ("device.bus_path", "pci-0000:00:14.0-usb-0:2:1.0")]),
+            device("alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono", &[("device.serial", "H390-1234"), ("device.bus_path", "pci-0000:00:14.0-usb-0:3:1.0")]),
+        ];
+        assert_eq!(headset_ids(&devices, IdentifyBy::Serial), vec![
+            "LX-3000@pci-0000:00:14.0-usb-0:1:1.0",
+            "LX-3000@pci-0000:00:14.0-usb-0:2:1.0",
+            "H390-1234",
+        ]);
+        assert_eq!(headset_ids(&devices, IdentifyBy::Port), vec![
+            "pci-0000:00:14.0-usb-0:1:1.0",
+            "pci-0000:00:14.0-usb-0:2:1.0",
+            "pci-0000:00:14.0-usb-0:3:1.0",
+        ]);
+    }
+}
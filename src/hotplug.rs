@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+
+use headset::{Headset, HeadsetId};
+use hub::Voice;
+
+
+#[derive(Debug, PartialEq)]
+pub enum DeviceChange {
+    Added(Voice, Headset),
+    Removed(Voice, Headset),
+}
+
+
+// Tracks which headsets are present between polls. Voices are handed out per headset
+// id and never reused for another headset, so a replugged headset gets its old voice
+// back and nobody else is renumbered.
+pub struct DeviceWatcher {
+    voices: HashMap<HeadsetId, Voice>,
+    present: BTreeMap<Voice, Headset>,
+}
+
+
+impl DeviceWatcher {
+    pub fn new() -> DeviceWatcher {
+        DeviceWatcher {
+            voices: HashMap::new(),
+            present: BTreeMap::new(),
+        }
+    }
+
+    fn voice(&mut self, id: &HeadsetId) -> Voice {
+        let next = self.voices.len();
+        *self.voices.entry(id.clone()).or_insert(next)
+    }
+
+    pub fn present(&self) -> &BTreeMap<Voice, Headset> {
+        &self.present
+    }
+
+    // compare a fresh enumeration against the previous one
+    pub fn update(&mut self, headsets: Vec<Headset>) -> Vec<DeviceChange> {
+        let mut changes = Vec::new();
+        let current = headsets.into_iter().map(|h| (self.voice(&h.id), h)).collect::<BTreeMap<Voice, Headset>>();
+
+        let removed = self.present.keys().filter(|v| !current.contains_key(v)).cloned().collect::<Vec<Voice>>();
+        for voice in removed {
+            let headset = self.present.remove(&voice).unwrap();
+            changes.push(DeviceChange::Removed(voice, headset));
+        }
+        for (voice, headset) in current {
+            if !self.present.contains_key(&voice) {
+                self.present.insert(voice, headset.clone());
+                changes.push(DeviceChange::Added(voice, headset));
+            }
+        }
+        changes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calibration::Calibration;
+    use headset::Headset;
+    use super::{DeviceChange, DeviceWatcher};
+
+    fn headset(id: &str) -> Headset {
+        Headset {
+            id: String::from(id),
+            source: format!("pulsesrc device={}", id),
+            sink: format!("pulsesink device={}", id),
+            calibration: Calibration { s2a: -50f64, a2s: -52f64 },
+            amplification: 20f64,
+            consent: false,
+        }
+    }
+
+    #[test]
+    fn test_replug_keeps_voice() {
+        let mut watcher = DeviceWatcher::new();
+        assert_eq!(watcher.update(vec![headset("a"), headset("b"), headset("c")]), vec![
+            DeviceChange::Added(0, headset("a")),
+            DeviceChange::Added(1, headset("b")),
+            DeviceChange::Added(2, headset("c")),
+        ]);
+        assert_eq!(watcher.update(vec![headset("a"), headset("b"), headset("c")]), vec![]);
+        assert_eq!(watcher.update(vec![headset("a"), headset("c")]), vec![
+            DeviceChange::Removed(1, headset("b")),
+        ]);
+        assert_eq!(watcher.update(vec![headset("d"), headset("a"), headset("c"), headset("b")]), vec![
+            DeviceChange::Added(1, headset("b")),
+            DeviceChange::Added(3, headset("d")),
+        ]);
+    }
+}
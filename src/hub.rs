@@ -1,10 +1,12 @@
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
 
-extern crate gst;
-use gst::Pipeline;
-use gst::ElementT;
-
-use levels::get_amplification;
+use backend::{Route, SharedBackend};
+use events::{Event, SharedEvents};
+use metrics::{Metrics, SharedMetrics};
+use headset::Headset;
+use recorder::Recorder;
+use status::{Status, VoiceStatus};
 
 pub type Voice = usize;
 
@@ -16,11 +18,30 @@ pub struct SilenceChange {
 }
 
 
-// This is the logic - mut free for easy testing
+// Everything the coordinator thread feeds into the hub
 #[derive(Debug)]
+pub enum Message {
+    Update(SilenceChange, f64), // and the rms that caused it
+    AddHeadset(Voice, Headset),
+    UpdateHeadset(Voice, Headset),
+    RemoveHeadset(Voice),
+    Fault(Voice, String),
+    Recovered(Voice),
+    RouteFailed(Voice, Voice, String),
+    ForceConnect(Voice, Voice),
+    ForceDisconnect(Voice),
+    Mute(Voice, bool),
+    Quit
+}
+
+
+// This is the logic - mut free for easy testing
+#[derive(Debug, Clone)]
 struct Egloorator {
+    voices: HashSet<Voice>,
     single: Option<Voice>,
     pairs: HashMap<Voice, Voice>,
+    active: HashSet<Voice>, // talking, going by the last silence change of each
 }
 
 
@@ -35,8 +56,10 @@ impl Egloorator {
 
     fn new(start: Vec<bool>) -> Egloorator {
         let mut er = Egloorator {
+            voices: (0..start.len()).collect(),
             single: None,
             pairs: HashMap::new(),
+            active: HashSet::new(),
         };
         for (i, silent) in start.iter().enumerate() {
             if !silent {
@@ -53,8 +76,12 @@ impl Egloorator {
     (), None + a => (), Some(a)
     (), Some(a) + b => ((a, b)) + None
     ((a, b)), None + -a => (), Some(b)
+    ((a, b)), Some(c) + -a => ((b, c)), None
     */
     fn input(&mut self, change: &SilenceChange) -> Vec<Action> {
+        if !self.voices.contains(&change.who) {
+            return Vec::new();
+        }
         if change.silent {
             self.input_off(change.who)
         } else {
@@ -62,9 +89,73 @@ impl Egloorator {
         }
     }
 
+    // new voices start out silent
+    fn add_voice(&mut self, who: Voice) {
+        self.voices.insert(who);
+    }
+
+    // Split the two without pairing either one again, they are left out until they
+    // next start talking. For the operator, and for routes that could not be built.
+    fn split_pair(&mut self, who: Voice) -> Vec<Action> {
+        match self.pairs.remove(&who) {
+            Some(other) => {
+                self.pairs.remove(&other);
+                vec![Action::Disconnect(who, other)]
+            },
+            None => Vec::new(),
+        }
+    }
+
+    // The audio between the two broke. Whoever of them is still talking goes back to
+    // pairing, with a waiting voice if there is one, else the two get fresh routes.
+    fn break_pair(&mut self, who: Voice) -> Vec<Action> {
+        let other = match self.pairs.get(&who) {
+            Some(&other) => other,
+            None => return Vec::new(),
+        };
+        let mut actions = self.split_pair(who);
+        for voice in vec![who, other] {
+            if self.active.contains(&voice) {
+                actions.extend(self.wait(voice));
+            }
+        }
+        actions
+    }
+
+    // Operator override: pair the two no matter who is talking. Their current partners
+    // go back to waiting if they are still talking.
+    fn force_pair(&mut self, one: Voice, two: Voice) -> Vec<Action> {
+        if one == two || !self.voices.contains(&one) || !self.voices.contains(&two) || self.pairs.get(&one) == Some(&two) {
+            return Vec::new();
+        }
+        let partners = vec![self.pairs.get(&one).cloned(), self.pairs.get(&two).cloned()];
+        let mut actions = self.split_pair(one);
+        actions.extend(self.split_pair(two));
+        if self.single == Some(one) || self.single == Some(two) {
+            self.single = None;
+        }
+        self.pairs.insert(one, two);
+        self.pairs.insert(two, one);
+        actions.push(Action::Connect(one, two));
+        for partner in partners.into_iter().filter_map(|p| p) {
+            if self.active.contains(&partner) {
+                actions.extend(self.wait(partner));
+            }
+        }
+        actions
+    }
+
+    // a removed voice is treated as going silent, its partner goes back to pairing
+    fn remove_voice(&mut self, who: Voice) -> Vec<Action> {
+        let actions = self.input_off(who);
+        self.voices.remove(&who);
+        actions
+    }
+
     fn input_off(&mut self, who: Voice) -> Vec<Action> {
         let mut actions = Vec::new();
 
+        self.active.remove(&who);
         if self.single == Some(who) {
             self.single = None;
         } else {
@@ -73,7 +164,10 @@ impl Egloorator {
                     self.pairs.remove(&who);
                     self.pairs.remove(&other);
                     actions.push(Action::Disconnect (who, other));
-                    self.single = Some(other);
+                    // a silent partner, forced in by the operator, does not wait
+                    if self.active.contains(&other) {
+                        actions.extend(self.wait(other));
+                    }
                 },
                 None => {
                 }
@@ -83,6 +177,16 @@ impl Egloorator {
     }
 
     fn input_on(&mut self, who: Voice) -> Vec<Action> {
+        self.active.insert(who);
+        // already talking to someone, e.g. forced together while silent
+        if self.pairs.contains_key(&who) {
+            return Vec::new();
+        }
+        self.wait(who)
+    }
+
+    // an active voice without a partner: take whoever is waiting, or wait
+    fn wait(&mut self, who: Voice) -> Vec<Action> {
         let mut actions = Vec::new();
 
         match self.single {
@@ -105,7 +209,10 @@ impl Egloorator {
 
 #[cfg(test)]
 mod tests {
-    use super::{Action, Egloorator, SilenceChange};
+    use std::collections::{BTreeSet, HashSet, VecDeque};
+    use quickcheck::{quickcheck, TestResult};
+    use sessions::pair;
+    use super::{Action, Egloorator, SilenceChange, Voice};
 
     #[test]
     fn test_sanity() {
@@ -131,88 +238,570 @@ mod tests {
         println!("{:?}", eg);
         assert_eq!(actions, vec![Action::Connect(1, 0)]);
     }
+
+    #[test]
+    fn test_add_remove_voice() {
+        let mut eg = Egloorator::new(vec![true; 2]);
+        let actions = eg.input(&SilenceChange { who: 5, silent: false});
+        assert_eq!(actions, vec![]);
+        assert_eq!(eg.single, None);
+
+        eg.add_voice(5);
+        eg.input(&SilenceChange { who: 0, silent: false});
+        let actions = eg.input(&SilenceChange { who: 5, silent: false});
+        assert_eq!(actions, vec![Action::Connect(5, 0)]);
+
+        let actions = eg.remove_voice(5);
+        assert_eq!(actions, vec![Action::Disconnect(5, 0)]);
+        assert_eq!(eg.single, Some(0));
+        let actions = eg.input(&SilenceChange { who: 5, silent: false});
+        assert_eq!(actions, vec![]);
+    }
+
+    #[test]
+    fn test_break_pair() {
+        let mut eg = Egloorator::new(vec![true; 3]);
+        eg.input(&SilenceChange { who: 0, silent: false});
+        eg.input(&SilenceChange { who: 1, silent: false});
+        // both are still talking, and nobody else is waiting
+        let actions = eg.break_pair(0);
+        assert_eq!(actions, vec![Action::Disconnect(0, 1), Action::Connect(1, 0)]);
+        assert_eq!(eg.single, None);
+        assert_eq!(eg.pairs.len(), 2);
+        let actions = eg.input(&SilenceChange { who: 2, silent: false});
+        assert_eq!(actions, vec![]);
+        assert_eq!(eg.single, Some(2));
+        let actions = eg.break_pair(0);
+        assert_eq!(actions, vec![Action::Disconnect(0, 1), Action::Connect(0, 2)]);
+        assert_eq!(eg.single, Some(1));
+        // split by the operator, 0 and 2 are left out while they keep talking
+        assert_eq!(eg.split_pair(2), vec![Action::Disconnect(2, 0)]);
+        assert_eq!(eg.single, Some(1));
+        assert_eq!(eg.split_pair(2), vec![]);
+        assert_eq!(eg.break_pair(0), vec![]);
+    }
+
+    #[test]
+    fn test_force_pair() {
+        let mut eg = Egloorator::new(vec![true; 5]);
+        eg.input(&SilenceChange { who: 0, silent: false});
+        eg.input(&SilenceChange { who: 1, silent: false});
+        eg.input(&SilenceChange { who: 2, silent: false});
+        let actions = eg.force_pair(1, 2);
+        assert_eq!(actions, vec![Action::Disconnect(1, 0), Action::Connect(1, 2)]);
+        // 0 is still talking and waits for the next one
+        assert_eq!(eg.single, Some(0));
+        assert_eq!(eg.pairs.get(&0), None);
+        assert_eq!(eg.force_pair(2, 1), vec![]);
+        assert_eq!(eg.force_pair(3, 3), vec![]);
+        assert_eq!(eg.force_pair(3, 7), vec![]);
+        // silent voices can be forced together, they split once one of them stops
+        let actions = eg.force_pair(3, 4);
+        assert_eq!(actions, vec![Action::Connect(3, 4)]);
+        let actions = eg.input(&SilenceChange { who: 3, silent: true});
+        assert_eq!(actions, vec![Action::Disconnect(3, 4)]);
+        // 4 is silent, nobody is connected to it when they next start talking
+        assert_eq!(eg.single, Some(0));
+        let actions = eg.input(&SilenceChange { who: 0, silent: true});
+        assert_eq!(actions, vec![]);
+        assert_eq!(eg.single, None);
+    }
+
+    #[test]
+    fn test_repeated_input_on() {
+        let mut eg = Egloorator::new(vec![true; 3]);
+        eg.input(&SilenceChange { who: 0, silent: false});
+        eg.input(&SilenceChange { who: 1, silent: false});
+        assert_eq!(eg.input(&SilenceChange { who: 1, silent: false}), vec![]);
+        assert_eq!(eg.single, None);
+        // forced together while silent, then one starts talking
+        eg.force_pair(0, 2);
+        assert_eq!(eg.single, Some(1));
+        assert_eq!(eg.input(&SilenceChange { who: 2, silent: false}), vec![]);
+        assert_eq!(eg.single, Some(1));
+    }
+
+    // Egloorator plus what its actions have done so far: which voices are silent, which
+    // connections are live and which of them the operator forced
+    #[derive(Debug, Clone)]
+    struct Model {
+        eg: Egloorator,
+        silent: Vec<bool>,
+        live: BTreeSet<(Voice, Voice)>,
+        forced: BTreeSet<(Voice, Voice)>,
+    }
+
+    impl Model {
+        fn new(n: usize) -> Model {
+            Model {
+                eg: Egloorator::new(vec![true; n]),
+                silent: vec![true; n],
+                live: BTreeSet::new(),
+                forced: BTreeSet::new(),
+            }
+        }
+
+        fn apply(&mut self, actions: Vec<Action>) -> Result<(), String> {
+            for action in actions {
+                match action {
+                    Action::Connect(one, two) => {
+                        if one == two || self.live.iter().any(|&(a, b)| a == one || b == one || a == two || b == two) {
+                            return Err(format!("connect {} {} while {:?} are live", one, two, self.live));
+                        }
+                        self.live.insert(pair(&one, &two));
+                    },
+                    Action::Disconnect(one, two) => {
+                        if !self.live.remove(&pair(&one, &two)) {
+                            return Err(format!("disconnect {} {} without a connect", one, two));
+                        }
+                        self.forced.remove(&pair(&one, &two));
+                    },
+                }
+            }
+            Ok(())
+        }
+
+        fn input(&mut self, who: Voice, silent: bool) -> Result<(), String> {
+            let actions = self.eg.input(&SilenceChange { who: who, silent: silent });
+            if self.eg.voices.contains(&who) {
+                self.silent[who] = silent;
+            }
+            self.apply(actions)?;
+            self.check()
+        }
+
+        // what the hub does on removal, a fault or a failed route
+        fn remove(&mut self, who: Voice) -> Result<(), String> {
+            let actions = self.eg.remove_voice(who);
+            self.silent[who] = true;
+            self.apply(actions)?;
+            self.check()
+        }
+
+        fn add(&mut self, who: Voice) -> Result<(), String> {
+            self.eg.add_voice(who);
+            self.check()
+        }
+
+        fn break_pair(&mut self, who: Voice) -> Result<(), String> {
+            let actions = self.eg.break_pair(who);
+            self.apply(actions)?;
+            self.check()
+        }
+
+        fn force(&mut self, one: Voice, two: Voice) -> Result<(), String> {
+            let actions = self.eg.force_pair(one, two);
+            let forced = actions.contains(&Action::Connect(one, two));
+            self.apply(actions)?;
+            if forced {
+                self.forced.insert(pair(&one, &two));
+            }
+            self.check()
+        }
+
+        fn check(&self) -> Result<(), String> {
+            let eg = &self.eg;
+            for (a, b) in &eg.pairs {
+                if a == b || eg.pairs.get(b) != Some(a) {
+                    return Err(format!("pairs not symmetric: {:?}", eg.pairs));
+                }
+                if self.silent[*a] && !self.forced.contains(&pair(a, b)) {
+                    return Err(format!("silent voice {} paired with {}", a, b));
+                }
+            }
+            match eg.single {
+                Some(single) if eg.pairs.contains_key(&single) => return Err(format!("{} is single and paired", single)),
+                Some(single) if self.silent[single] => return Err(format!("silent voice {} is single", single)),
+                _ => {}
+            }
+            // so at most one active voice is unpaired
+            for voice in &eg.voices {
+                if !self.silent[*voice] && eg.single != Some(*voice) && !eg.pairs.contains_key(voice) {
+                    return Err(format!("active voice {} is neither single nor paired", voice));
+                }
+            }
+            let pairs = eg.pairs.iter().map(|(a, b)| pair(a, b)).collect::<BTreeSet<(Voice, Voice)>>();
+            if pairs != self.live {
+                return Err(format!("pairs {:?} but live connections {:?}", pairs, self.live));
+            }
+            Ok(())
+        }
+
+        // everything that tells the states apart
+        fn state(&self) -> (Vec<Voice>, Option<Voice>, Vec<(Voice, Voice)>, Vec<(Voice, Voice)>, Vec<bool>) {
+            let mut voices = self.eg.voices.iter().cloned().collect::<Vec<Voice>>();
+            voices.sort();
+            (voices, self.eg.single, self.live.iter().cloned().collect(), self.forced.iter().cloned().collect(), self.silent.clone())
+        }
+    }
+
+    #[test]
+    fn test_pairing_invariants() {
+        fn pairing(voices: u8, changes: Vec<(u8, bool)>) -> TestResult {
+            let n = 2 + voices as usize % 5;
+            let mut model = Model::new(n);
+            for (who, silent) in changes {
+                match model.input(who as usize % n, silent) {
+                    Ok(()) => {},
+                    Err(e) => return TestResult::error(e),
+                }
+            }
+            TestResult::passed()
+        }
+        quickcheck(pairing as fn(u8, Vec<(u8, bool)>) -> TestResult);
+    }
+
+    // Every state reachable from all silent, for a few voices: voices talking and
+    // stopping, being removed and added back, routes failing and the operator forcing
+    // pairs
+    #[test]
+    fn test_exhaustive() {
+        for n in 1..5 {
+            let start = Model::new(n);
+            let mut seen = HashSet::new();
+            seen.insert(start.state());
+            let mut queue = VecDeque::new();
+            queue.push_back((start, vec![]));
+            while let Some((model, path)) = queue.pop_front() {
+                let mut steps: Vec<(String, Box<Fn(&mut Model) -> Result<(), String>>)> = Vec::new();
+                for who in 0..n {
+                    steps.push((format!("{} talks", who), Box::new(move |m: &mut Model| m.input(who, false))));
+                    steps.push((format!("{} stops", who), Box::new(move |m: &mut Model| m.input(who, true))));
+                    steps.push((format!("{} removed", who), Box::new(move |m: &mut Model| m.remove(who))));
+                    steps.push((format!("{} added", who), Box::new(move |m: &mut Model| m.add(who))));
+                    steps.push((format!("{} route failed", who), Box::new(move |m: &mut Model| m.break_pair(who))));
+                    for other in (0..n).filter(|&other| other != who) {
+                        steps.push((format!("{} forced with {}", who, other), Box::new(move |m: &mut Model| m.force(who, other))));
+                    }
+                }
+                for (what, step) in steps {
+                    let mut next = model.clone();
+                    let mut next_path = path.clone();
+                    next_path.push(what);
+                    match step(&mut next) {
+                        Ok(()) => {},
+                        Err(e) => panic!("{} voices, after {:?}: {}", n, next_path, e),
+                    }
+                    if seen.insert(next.state()) {
+                        queue.push_back((next, next_path));
+                    }
+                }
+            }
+            assert!(seen.len() > 1);
+        }
+    }
 }
 
 
-pub struct Hub {
-    pipes: Vec<Vec<Option<Pipeline>>>,
-    sources: Vec<String>,
-    sinks: Vec<String>,
-    eg: Egloorator,
+// Pipeline trouble per voice. Voices are per headset id, so this follows the headset
+// across replugs.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub faulted: bool,
+    pub restarts: u32,
+    pub last_error: Option<String>,
 }
 
 
-fn make_simplex_pipeline(source: &String, sink: &String) -> String {
-    let amplification = get_amplification(source);
-    format!("{} ! audioamplify amplification={} ! {}", source, amplification, sink)
+pub struct Hub {
+    tx: Sender<Message>,
+    backend: SharedBackend,
+    pipes: HashMap<(Voice, Voice), Box<Route>>,
+    headsets: HashMap<Voice, Headset>,
+    health: HashMap<Voice, Health>,
+    eg: Egloorator,
+    muted: HashSet<Voice>,
+    recorder: Option<Recorder>,
+    events: Option<SharedEvents>,
+    metrics: Option<SharedMetrics>,
 }
 
 
 impl Hub {
-    pub fn new(sources: &Vec<String>, sinks: &Vec<String>) -> Hub
+    // tx is where route failures are reported, i.e. back to the coordinator
+    pub fn new(tx: Sender<Message>, backend: SharedBackend) -> Hub
     {
-        let mut pipes: Vec<Vec<Option<Pipeline>>> = Vec::new();
+        Hub {
+            tx: tx,
+            backend: backend,
+            pipes: HashMap::new(),
+            headsets: HashMap::new(),
+            health: HashMap::new(),
+            eg: Egloorator::new(vec![]),
+            muted: HashSet::new(),
+            recorder: None,
+            events: None,
+            metrics: None,
+        }
+    }
 
-        for source_i in 0..sources.len() {
-            pipes.push(Vec::new());
-            for _ in 0..sinks.len() {
-                pipes[source_i].push(None);
-            }
+    // One message from the coordinator's channel, false once it is time to quit
+    pub fn handle(&mut self, msg: Message) -> bool
+    {
+        debug!("sending {:?} to hub", msg);
+        match msg {
+            Message::Update(silence_change, rms) => self.input(&silence_change, rms),
+            Message::AddHeadset(voice, headset) => self.add_headset(voice, headset),
+            Message::UpdateHeadset(voice, headset) => self.update_headset(voice, headset),
+            Message::RemoveHeadset(voice) => self.remove_headset(voice),
+            Message::Fault(voice, reason) => self.fault(voice, &reason),
+            Message::Recovered(voice) => self.recovered(voice),
+            Message::RouteFailed(from, to, reason) => self.route_failed(from, to, &reason),
+            Message::ForceConnect(one, two) => self.force_connect(one, two),
+            Message::ForceDisconnect(voice) => self.force_disconnect(voice),
+            Message::Mute(voice, muted) => self.mute(voice, muted),
+            Message::Quit => return false,
         }
+        true
+    }
 
-        Hub {
-            pipes: pipes,
-            sources: sources.clone(),
-            sinks: sinks.clone(),
-            eg: Egloorator::new(vec![true; sources.len()]),
+    // record every conversation from now on
+    pub fn set_recorder(&mut self, recorder: Recorder)
+    {
+        self.recorder = Some(recorder);
+    }
+
+    // tell subscribers about silence changes and conversations
+    pub fn set_events(&mut self, events: SharedEvents)
+    {
+        self.events = Some(events);
+    }
+
+    fn publish_event(&self, event: Event)
+    {
+        match self.events {
+            Some(ref events) => events.publish(event),
+            None => {}
         }
     }
 
-    fn connect_simplex(&mut self, one: Voice, two:Voice)
+    // count transitions, conversations and failures
+    pub fn set_metrics(&mut self, metrics: SharedMetrics)
     {
-        let s = make_simplex_pipeline(&self.sources[one], &self.sinks[two]);
-        let mut pipe = gst::Pipeline::new_from_str(&*s).unwrap();
-        pipe.play();
-        self.pipes[one][two] = Some(pipe);
+        self.metrics = Some(metrics);
     }
 
-    fn connect(&mut self, one: Voice, two: Voice)
+    fn with_metrics<F: FnOnce(&Metrics)>(&self, f: F)
     {
-        self.connect_simplex(one, two);
-        self.connect_simplex(two, one);
+        match self.metrics {
+            Some(ref metrics) => f(metrics),
+            None => {}
+        }
     }
 
-    fn disconnect_simplex(&mut self, one: Voice, two: Voice)
+    fn ids(&self, one: Voice, two: Voice) -> (String, String)
+    {
+        (self.headsets[&one].id.clone(), self.headsets[&two].id.clone())
+    }
+
+    pub fn add_headset(&mut self, voice: Voice, headset: Headset)
     {
-        match self.pipes[one][two] {
-            Some(ref mut pipe) => {
-                pipe.pause(); // TODO: any better way to dispose? drop? that is probably unsafe.
+        info!("voice {}: adding headset {}", voice, headset.id);
+        self.headsets.insert(voice, headset);
+        // a replugged headset starts with a fresh level pipeline
+        match self.health.get_mut(&voice) {
+            Some(health) => health.faulted = false,
+            None => {}
+        }
+        self.rejoin(voice);
+    }
+
+    // back into pairing, unless something still keeps the voice out
+    fn rejoin(&mut self, voice: Voice)
+    {
+        let faulted = self.health.get(&voice).map(|h| h.faulted).unwrap_or(false);
+        if self.headsets.contains_key(&voice) && !faulted && !self.muted.contains(&voice) {
+            self.eg.add_voice(voice);
+        }
+    }
+
+    // new settings after a config reload, used by conversations started from now on
+    pub fn update_headset(&mut self, voice: Voice, headset: Headset)
+    {
+        match self.headsets.get_mut(&voice) {
+            Some(current) => *current = headset,
+            None => {}
+        }
+    }
+
+    // hang up any conversation the headset is in before forgetting it
+    pub fn remove_headset(&mut self, voice: Voice)
+    {
+        let actions = self.eg.remove_voice(voice);
+        self.apply(actions);
+        match self.headsets.remove(&voice) {
+            Some(headset) => info!("voice {}: removed headset {}", voice, headset.id),
+            None => {}
+        }
+    }
+
+    // hang up every conversation and forget all headsets
+    pub fn shutdown(&mut self)
+    {
+        let voices = self.headsets.keys().cloned().collect::<Vec<Voice>>();
+        for voice in voices {
+            self.remove_headset(voice);
+        }
+    }
+
+    // The level pipeline failed and is being restarted. Take the voice out of pairing
+    // until it reports levels again.
+    pub fn fault(&mut self, voice: Voice, reason: &String)
+    {
+        let actions = self.eg.remove_voice(voice);
+        self.apply(actions);
+        let health = self.health.entry(voice).or_insert(Health::default());
+        health.faulted = true;
+        health.restarts += 1;
+        health.last_error = Some(reason.clone());
+        let id = self.headsets.get(&voice).map(|h| h.id.clone()).unwrap_or(format!("?"));
+        warn!("voice {} ({}): faulted, restart #{}: {}", voice, id, health.restarts, reason);
+        self.with_metrics(|m| m.error(voice, &id));
+        self.publish_event(Event::Error { voice: voice, id: id, error: reason.clone() });
+    }
+
+    pub fn recovered(&mut self, voice: Voice)
+    {
+        match self.health.get_mut(&voice) {
+            Some(ref mut health) if health.faulted => {
+                health.faulted = false;
+                info!("voice {}: recovered after {} restarts", voice, health.restarts);
             },
+            _ => return,
+        }
+        let id = self.headsets.get(&voice).map(|h| h.id.clone()).unwrap_or(format!("?"));
+        let restarts = self.health[&voice].restarts;
+        self.with_metrics(|m| m.restart(voice, &id));
+        self.publish_event(Event::Restart { voice: voice, id: id, restarts: restarts });
+        self.rejoin(voice);
+    }
+
+    // Operator override, a muted voice is kept out of pairing until unmuted. It starts
+    // out silent when unmuted, pairing resumes the next time it becomes active.
+    pub fn mute(&mut self, voice: Voice, muted: bool)
+    {
+        if muted {
+            self.muted.insert(voice);
+            let actions = self.eg.remove_voice(voice);
+            self.apply(actions);
+        } else {
+            self.muted.remove(&voice);
+            self.rejoin(voice);
+        }
+        info!("voice {}: muted {}", voice, muted);
+    }
+
+    pub fn force_connect(&mut self, one: Voice, two: Voice)
+    {
+        let actions = self.eg.force_pair(one, two);
+        self.apply(actions);
+    }
+
+    pub fn force_disconnect(&mut self, voice: Voice)
+    {
+        let actions = self.eg.split_pair(voice);
+        self.apply(actions);
+    }
+
+    // fill in everything but the levels, which the level watchers keep up to date
+    pub fn publish(&self, status: &mut Status)
+    {
+        let headsets = &self.headsets;
+        status.voices.retain(|voice, v| v.offline || headsets.contains_key(voice));
+        for (voice, headset) in headsets {
+            let health = self.health.get(voice).cloned().unwrap_or(Health::default());
+            let entry = status.voices.entry(*voice).or_insert(VoiceStatus::default());
+            entry.id = headset.id.clone();
+            entry.s2a = headset.calibration.s2a;
+            entry.a2s = headset.calibration.a2s;
+            entry.amplification = headset.amplification;
+            entry.muted = self.muted.contains(voice);
+            entry.faulted = health.faulted;
+            entry.restarts = health.restarts;
+            entry.last_error = health.last_error;
+        }
+        status.single = self.eg.single;
+        status.pairs = self.eg.pairs.iter().filter(|&(a, b)| a < b).map(|(a, b)| (*a, *b)).collect();
+        status.pairs.sort();
+        status.routes = self.pipes.keys().cloned().collect();
+        status.routes.sort();
+    }
+
+    fn connect_simplex(&mut self, one: Voice, two:Voice) -> Result<(), String>
+    {
+        let tx = self.tx.clone();
+        let failed = Box::new(move |reason: String| { tx.send(Message::RouteFailed(one, two, reason)).ok(); });
+        let route = self.backend.route(&self.headsets[&one], &self.headsets[&two], failed)?;
+        self.pipes.insert((one, two), route);
+        Ok(())
+    }
+
+    fn connect(&mut self, one: Voice, two: Voice) -> Result<(), String>
+    {
+        info!("connect {} <-> {}", self.headsets[&one].id, self.headsets[&two].id);
+        self.connect_simplex(one, two)?;
+        self.connect_simplex(two, one)?;
+        match self.recorder {
+            Some(ref mut recorder) => recorder.start(one, two, &self.headsets[&one], &self.headsets[&two]),
+            None => {}
+        }
+        let ids = self.ids(one, two);
+        self.with_metrics(|m| m.connect((one, two), &ids));
+        self.publish_event(Event::Connect { voices: (one, two), ids: ids });
+        Ok(())
+    }
+
+    // Called when a route's bus reported an error. Stale reports for routes that were
+    // already torn down are ignored.
+    pub fn route_failed(&mut self, one: Voice, two: Voice, reason: &String)
+    {
+        if !self.pipes.contains_key(&(one, two)) {
+            return;
+        }
+        error!("route {} -> {} failed: {}", one, two, reason);
+        let id = self.headsets.get(&one).map(|h| h.id.clone()).unwrap_or(format!("?"));
+        self.with_metrics(|m| m.error(one, &id));
+        self.publish_event(Event::Error { voice: one, id: id, error: format!("route {} -> {}: {}", one, two, reason) });
+        let actions = self.eg.break_pair(one);
+        self.apply(actions);
+    }
+
+    fn disconnect_simplex(&mut self, one: Voice, two: Voice)
+    {
+        match self.pipes.remove(&(one, two)) {
+            Some(route) => route.stop(),
             None => {
             }
         }
-        self.pipes[one][two] = None;
     }
 
     fn disconnect(&mut self, one: Voice, two: Voice)
     {
+        info!("disconnect {} <-> {}", self.headsets[&one].id, self.headsets[&two].id);
         self.disconnect_simplex(one, two);
         self.disconnect_simplex(two, one);
+        match self.recorder {
+            Some(ref mut recorder) => recorder.stop(one, two),
+            None => {}
+        }
+        let ids = self.ids(one, two);
+        self.with_metrics(|m| m.disconnect((one, two), &ids));
+        self.publish_event(Event::Disconnect { voices: (one, two), ids: ids });
     }
 
-    // This also toggles all of the pipelines. It would be nicer if we could do this
-    // via gstreamer, as a control flow? my ascii art fails me. Something like:
-    // hub -> [play_bit(pipeline) for pipeline in pipelines]
-    pub fn input(&mut self, msg: &SilenceChange)
+    fn apply(&mut self, actions: Vec<Action>)
     {
-        //println!("got {:?}", msg);
-        let actions = self.eg.input(msg);
-        println!("{:?}", self.eg);
-
         for action in actions {
             match action {
                 Action::Connect(one, two) => {
-                    self.connect(one, two);
+                    match self.connect(one, two) {
+                        Ok(()) => {},
+                        Err(e) => {
+                            error!("{}", e);
+                            let actions = self.eg.split_pair(one);
+                            self.apply(actions);
+                        }
+                    }
                 },
                 Action::Disconnect(one, two) => {
                     self.disconnect(one, two);
@@ -220,4 +809,29 @@ impl Hub {
             }
         }
     }
+
+    // This also toggles all of the pipelines. It would be nicer if we could do this
+    // via gstreamer, as a control flow? my ascii art fails me. Something like:
+    // hub -> [play_bit(pipeline) for pipeline in pipelines]
+    pub fn input(&mut self, msg: &SilenceChange, rms: f64)
+    {
+        //println!("got {:?}", msg);
+        match self.headsets.get(&msg.who) {
+            Some(headset) => {
+                self.with_metrics(|m| m.transition(msg.who, &headset.id, msg.silent));
+                self.publish_event(Event::SilenceChange {
+                    voice: msg.who,
+                    id: headset.id.clone(),
+                    silent: msg.silent,
+                    rms: rms,
+                    s2a: headset.calibration.s2a,
+                    a2s: headset.calibration.a2s,
+                });
+            },
+            None => {}
+        }
+        let actions = self.eg.input(msg);
+        debug!("{:?}", self.eg);
+        self.apply(actions);
+    }
 }
@@ -7,7 +7,7 @@ pub fn get_levels(source: &String) -> (f64, f64)
     if source.contains("alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono") {
         return (-55f64, -57f64);
     }
-    // TODO - better identifier. serial of card?
+    // per headset (rather than per model) values belong in the calibration store
     if source == "pulsesrc device=alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono.2" {
         return (-32f64, -34f64)
     }
@@ -20,17 +20,23 @@ pub fn get_levels(source: &String) -> (f64, f64)
     if source.contains("alsa_input.usb-Generic_USB_Ear-Microphone_0000000001-00.analog-stereo") {
         return (-50f64, -52f64)
     }
-    println!("matching default source");
+    debug!("matching default source");
     (-56f64, -58f64)
 }
 
 
-pub fn get_amplification(source: &String) -> f64
+// short human readable name used for recording file names
+pub fn get_short_name(source: &String) -> String
 {
-    let (s2a, a2s) = get_levels(source);
-    let amplification =  -30f64 - s2a;
-
-    println!("amplifying {} by {}", source, amplification);
-    amplification
+    let name = match &source[..] {
+        "alsa_input.usb-Generic_USB_Ear-Microphone_0000000001-00.analog-stereo" => "generic",
+        "alsa_input.usb-Logitech_Inc._Logitech_USB_Headset_H340-00.analog-stereo" => "logi-h340",
+        "alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono" => "logi-h390",
+        "alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono" => "ms-lx-3000",
+        "alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono.2" => "ms-lx-3000-2nd",
+        "alsa_input.usb-Microsoft_Microsoft_LifeChat_LX-4000-00.analog-stereo" => "ms-lx-4000",
+        "alsa_input.pci-0000_00_1b.0.analog-stereo" => "internal",
+        _ => source,
+    };
+    String::from(name)
 }
-
@@ -0,0 +1,93 @@
+use std::io::{stdout, stderr};
+use std::process;
+
+use argparse::{ArgumentParser, Store, StoreFalse, Collect};
+
+use devices::{Device, DeviceProvider, PulseAudio};
+use headset::{headset_ids, IdentifyBy};
+use sinks::{match_sinks, parse_overrides};
+use sources::SourceFilter;
+
+
+fn describe(device: &Device) -> String {
+    format!("{}, {}ch {}Hz, card {}", device.description, device.channels, device.sample_rate, device.card().unwrap_or("?"))
+}
+
+
+// Show every source, whether the current filters select it and why, and the sink and
+// headset id each selected source would get.
+pub fn main(args: Vec<String>)
+{
+    let mut filter_sources: Vec<String> = vec![];
+    let mut filter_not_sources: Vec<String> = vec![];
+    let mut usb_only = true;
+    let mut sink_overrides: Vec<String> = vec![];
+    let mut identify_by: String = format!("serial");
+
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("List audio devices and which of them would be used");
+        ap.refer(&mut filter_sources).add_option(&["-i", "--filter-sources"], Collect, "Only use sources matching this regex (repeatable)");
+        ap.refer(&mut filter_not_sources).add_option(&["-x", "--filter-not-sources"], Collect, "Skip sources matching this regex (repeatable)");
+        ap.refer(&mut usb_only).add_option(&["--all-buses"], StoreFalse, "Also use non usb sources (bluetooth, built in)");
+        ap.refer(&mut sink_overrides).add_option(&["-k", "--sink"], Collect, "source=sink, override the sink matched to a source");
+        ap.refer(&mut identify_by).add_option(&["--identify-by"], Store, "serial or port, how headset ids are derived");
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {},
+            Err(x) => process::exit(x),
+        }
+    }
+
+    let filter = SourceFilter::new(&filter_sources, &filter_not_sources, usb_only).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(2);
+    });
+    let overrides = parse_overrides(&sink_overrides).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(2);
+    });
+    let identify_by = IdentifyBy::parse(&identify_by).unwrap_or_else(|| {
+        println!("unknown --identify-by {}, expected serial or port", identify_by);
+        process::exit(2);
+    });
+    let (sources, sinks) = match (PulseAudio.sources(), PulseAudio.sinks()) {
+        (Ok(sources), Ok(sinks)) => (sources, sinks),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("cannot list devices: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let selected = sources.iter().filter(|s| filter.check(s).selected).cloned().collect::<Vec<Device>>();
+    let sink_match = match_sinks(&selected, &sinks, &overrides);
+    let ids = headset_ids(&selected, identify_by);
+
+    println!("sources:");
+    for source in &sources {
+        let verdict = filter.check(source);
+        println!("  [{}] {}", if verdict.selected { "x" } else { " " }, source.name);
+        println!("      {}", describe(source));
+        println!("      {}", verdict.reason);
+        match selected.iter().position(|s| s.name == source.name) {
+            Some(i) => {
+                println!("      id: {}", ids[i]);
+                match sink_match.sinks[i] {
+                    Some(ref sink) => println!("      sink: {}", sink),
+                    None => println!("      sink: none found, will not be used (use --sink to set one)"),
+                }
+            },
+            None => {},
+        }
+    }
+
+    println!("sinks without a source:");
+    for sink in &sink_match.unmatched_sinks {
+        println!("  {}", sink);
+    }
+    if !sink_match.unknown_sinks.is_empty() {
+        println!("override sinks that do not exist:");
+        for sink in &sink_match.unknown_sinks {
+            println!("  {}", sink);
+        }
+    }
+}
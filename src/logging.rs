@@ -0,0 +1,78 @@
+use env_logger::Builder;
+use log::LevelFilter;
+
+use config::LoggingConfig;
+
+
+// Leveled logging to stderr. The config sets a level for everything and optionally
+// per module, each -v raises all of them one step: info, debug (hub state, messages
+// to the hub), trace (every rms reading).
+
+const LEVELS: &[LevelFilter] = &[LevelFilter::Off, LevelFilter::Error, LevelFilter::Warn,
+                                 LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.parse::<LevelFilter>().map_err(|_| format!("unknown log level `{}`, expected off, error, warn, info, debug or trace", level))
+}
+
+
+fn raise(level: LevelFilter, by: usize) -> LevelFilter {
+    let i = LEVELS.iter().position(|l| *l == level).unwrap_or(0);
+    LEVELS[(i + by).min(LEVELS.len() - 1)]
+}
+
+
+// hub and egloorator::hub are the same module, egloorator alone is main.rs
+fn module(name: &str) -> String {
+    if name == "egloorator" || name.contains("::") {
+        String::from(name)
+    } else {
+        format!("egloorator::{}", name)
+    }
+}
+
+
+// the level of everything, then of each configured module
+pub fn filters(config: &LoggingConfig, verbose: usize) -> Result<(LevelFilter, Vec<(String, LevelFilter)>), String> {
+    let level = raise(parse_level(&config.level)?, verbose);
+    let mut modules = Vec::new();
+    for (name, level) in &config.modules {
+        modules.push((module(name), raise(parse_level(level)?, verbose)));
+    }
+    Ok((level, modules))
+}
+
+
+// once at startup, a reloaded config keeps the levels it started with
+pub fn init(config: &LoggingConfig, verbose: usize) -> Result<(), String> {
+    let (level, modules) = filters(config, verbose)?;
+    let mut builder = Builder::new();
+    builder.filter(None, level);
+    for (name, level) in modules {
+        builder.filter(Some(&name), level);
+    }
+    builder.try_init().map_err(|e| e.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+    use config::LoggingConfig;
+    use super::filters;
+
+    #[test]
+    fn test_filters() {
+        let mut config = LoggingConfig::default();
+        config.modules.insert(format!("hub"), format!("debug"));
+        config.modules.insert(format!("egloorator"), format!("warn"));
+        assert_eq!(filters(&config, 0), Ok((LevelFilter::Info, vec![(format!("egloorator"), LevelFilter::Warn),
+                                                                     (format!("egloorator::hub"), LevelFilter::Debug)])));
+        // -vv
+        assert_eq!(filters(&config, 2), Ok((LevelFilter::Trace, vec![(format!("egloorator"), LevelFilter::Debug),
+                                                                      (format!("egloorator::hub"), LevelFilter::Trace)])));
+        config.level = format!("loud");
+        assert!(filters(&config, 0).is_err());
+    }
+}
@@ -1,254 +1,311 @@
 extern crate argparse;
 extern crate gst;
 extern crate gtk;
+extern crate gdk;
+extern crate glib;
 extern crate gobject_sys;
+extern crate chrono;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate regex;
+extern crate libc;
+extern crate toml;
+extern crate tiny_http;
+extern crate sha1;
+extern crate base64;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+#[cfg(test)]
+extern crate quickcheck;
 
-use std::process::Command;
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::thread;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant, SystemTime};
 
-use gst::ElementT;
-use argparse::{ArgumentParser, StoreTrue, Store, Collect};
-//use gtk::prelude::*;
+use argparse::{ArgumentParser, StoreTrue, StoreFalse, Store, Collect, IncrBy};
+
+mod backoff;
 
 mod silence;
-use silence::Silence;
 
 mod gst_helpers;
-use gst_helpers::{gst_message_get_double, gst_message_get_name};
+use gst_helpers::gst_wait_finalized;
 
 mod hub;
-use hub::{Hub, SilenceChange};
+use hub::{Hub, Message};
 
 mod levels;
-use levels::{get_levels, get_amplification};
 
+mod calibration;
+use calibration::CalibrationStore;
 
-#[derive(Debug)]
-enum Message {
-    Update(SilenceChange),
-    Quit
-}
+mod config;
+use config::Config;
 
+mod logging;
 
-// run a subprocess and provide it's output back as a String
-fn check_output(cmd: &str, arguments: Vec<&str>) -> String
-{
-    let mut p = Command::new(cmd);
-    for arg in arguments.iter() {
-        p.arg(arg);
-    }
-    let output = p.output().unwrap();
-    String::from_utf8_lossy(
-        if output.status.success() {
-            &output.stdout
-        } else {
-            &output.stderr
-        }
-    ).into_owned()
-}
+mod headset;
+use headset::{Discovery, Headset, IdentifyBy, file_headset_id};
 
+mod hotplug;
+use hotplug::DeviceWatcher;
 
-fn get_sources(filter_sources: Option<&String>, filter_not_sources:Option<&String>) -> Vec<String>
-{
-    // would be nice to have list comprehensions
-    let mut out = Vec::<String>::new();
-    println!("filter sources:      {:?}", filter_sources);
-    println!("filter not sources:  {:?}", filter_not_sources);
-    for l in check_output("pactl", vec!["list", "short", "sources"]).split("\n") {
-        let v = l.split("\t").collect::<Vec<&str>>();
-        let n = v.len();
-        if n < 2 {
-            continue;
-        }
-        let source = String::from(v[1]);
-        if source.contains("monitor") || !source.contains("usb")
-            // || !source.contains("Microsoft")
-            // || source != "alsa_input.usb-Microsoft_Microsoft_LifeChat_LX-4000-00.analog-stereo"
-            // || !source.contains("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000")
-        {
-            continue;
-        }
-        match filter_sources {
-            None => {},
-            Some(s) => {
-                if !source.contains(s) {
-                    continue;
-                }
-            }
-        };
+mod devices;
+use devices::PulseAudio;
 
-        match filter_not_sources {
-            None => {},
-            Some(s) => {
-                if source.contains(s) {
-                    continue;
-                }
-            }
-        }
-        out.push(source);
+mod sinks;
+use sinks::parse_overrides;
+
+mod sources;
+use sources::SourceFilter;
+
+mod record;
+
+mod list_devices;
+
+mod report;
+
+mod sessions;
+
+mod trace;
+
+mod replay;
+
+mod signals;
+
+mod recorder;
+use recorder::Recorder;
+
+mod status;
+use status::{SharedStatus, Status};
+
+mod control;
+
+mod events;
+use events::{Event, Events, Level};
+
+mod eventlog;
+use eventlog::EventLog;
+
+mod websocket;
+
+mod gui;
+
+mod osc;
+
+mod artnet;
+
+mod metrics;
+use metrics::Metrics;
+
+mod backend;
+use backend::{GstBackend, SharedBackend};
+
+mod watchers;
+use watchers::Watchers;
+
+#[cfg(test)]
+mod simulation;
+
+#[cfg(test)]
+mod scenario;
+
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|s| &s[..]) {
+        Some("record") => record::main(args[1..].to_vec()),
+        Some("list-devices") => list_devices::main(args[1..].to_vec()),
+        Some("report") => report::main(args[1..].to_vec()),
+        Some("replay") => replay::main(args[1..].to_vec()),
+        _ => run(),
     }
-    out
 }
 
 
-const level_interval: f64 = 0.1f64;
-static silent_period: i64 = 10 * 30; // 1 seconds
-static average_period: i64 = 1; // no averaging - let level element do that
-static mut sine_timeout: u64 = (1.0f64 / level_interval) as u64; // 0 for no timeout, i.e. debug mode
+// Command line settings, they take precedence over (or add to) the config file
+struct Options {
+    filter_sources: Vec<String>,
+    filter_not_sources: Vec<String>,
+    usb_only: bool,
+    sink_overrides: Vec<String>,
+    identify_by: String,
+    calibration_file: String,
+    record_consent: Vec<String>,
+    debug: bool,
+}
 
 
-fn watch_level(index: usize, level_source: &String, sink: &String, level_pipeline: &mut gst::Pipeline, tx: &Sender<Message>)
+fn make_config(config_file: &String, options: &Options) -> Result<Config, String>
 {
-    let mut prev = true;
-    let (s2a, a2s) = get_levels(&level_source);
-    println!("{}: s2a {}, a2s {}", level_source, s2a, a2s);
-    let mut silence = Silence::new(s2a, a2s, silent_period, average_period);
-    let mut level_bus = level_pipeline.bus().expect("Couldn't get bus from pipeline");
-    let level_bus_receiver = level_bus.receiver();
-
-    let sine_timeout_max: u64 = unsafe {
-        sine_timeout
+    let mut config = match config_file.len() {
+        0 => Config::default(),
+        _ => Config::load(Path::new(config_file))?,
     };
-
-    let play_sine_on_activity = sink.starts_with("pulsesink");
-    let mut sine_timeout_counter = 0u64;
-    let mut sine_pipeline = gst::Pipeline::new_from_str("fakesrc ! fakesink").unwrap();
-    if play_sine_on_activity {
-        let sine_str = format!("ladspasrc-sine-so-sine-fcac amplitude=0.02 ! pulsesink device={}", sink);
-        sine_pipeline = gst::Pipeline::new_from_str(sine_str.as_ref()).unwrap();
+    config.devices.filter_sources.extend(options.filter_sources.iter().cloned());
+    config.devices.filter_not_sources.extend(options.filter_not_sources.iter().cloned());
+    config.devices.all_buses = config.devices.all_buses || !options.usb_only;
+    config.devices.record_consent.extend(options.record_consent.iter().cloned());
+    if options.identify_by.len() > 0 {
+        config.devices.identify_by = options.identify_by.clone();
     }
-
-    for message in level_bus_receiver.iter() {
-        match message.parse() {
-            gst::Message::StateChangedParsed{ref msg, ref old, ref new, ref pending} => {
-                //println!("element `{}` changed from {:?} to {:?}", message.src_name(), old, new);
-            }
-            gst::Message::ErrorParsed{ref msg, ref error, ref debug} => {
-                //println!("error msg from element `{}`: {}, quitting", message.src_name(), error.message());
-                break;
-            }
-            gst::Message::Eos(ref msg) => {
-                println!("eos received quiting");
-                tx.send(Message::Quit);
-                break;
-            }
-            _ => {
-                // level sends messages, look for rms, peak and decay doubles in the structure
-                match gst_message_get_name(&message) {
-                    Some(the_name) => {
-                        if &*the_name == "level" {
-                            let rms = gst_message_get_double(&message, "rms");
-                            silence = silence.input(rms);
-                            println!("{}: {}: rms = {}", the_name, message.src_name(), rms);
-                            let output = silence.output();
-                            match (output, output != prev) {
-                                (true, true) => {
-                                    println!("{}: became silent! {}", level_source, rms);
-                                    if play_sine_on_activity {
-                                        sine_pipeline.pause();
-                                    }
-                                    tx.send(Message::Update(SilenceChange{who: index, silent: true})).unwrap();
-                                },
-                                (false, true) => {
-                                    println!("{}: became active! {}", level_source, rms);
-                                    if play_sine_on_activity {
-                                        sine_pipeline.play();
-                                        sine_timeout_counter = 5u64; // hardcoded, should be relative to level period, currently 0.1s
-                                    }
-                                    tx.send(Message::Update(SilenceChange{who: index, silent: false})).unwrap();
-                                },
-                                _ => {
-                                    if play_sine_on_activity {
-                                        if sine_timeout_max != 0 && sine_timeout_counter == 0 {
-                                            sine_pipeline.pause();
-                                        }
-                                        if sine_timeout_counter > 0 {
-                                            sine_timeout_counter -= 1;
-                                        }
-                                    }
-                                },
-                            }
-                            prev = output;
-                        } else {
-                            //println!("ignoring message {}", the_name);
-                        }
-                    }
-                    None => {
-                        println!("msg of type `{}` from element `{}`", message.type_name(), message.src_name());
-                    }
-                }
-            }
-        }
+    if options.calibration_file.len() > 0 {
+        config.devices.calibration = Some(options.calibration_file.clone());
+    }
+    if options.debug {
+        config.cues.sine_while_active = true;
     }
+    Ok(config)
 }
 
 
-fn main() {
-    let mut verbose = false;
+fn make_discovery(config: &Config, options: &Options) -> Result<Discovery, String>
+{
+    let mut sink_overrides = config.devices.sinks.iter().map(|(k, v)| (k.clone(), v.clone())).collect::<Vec<(String, String)>>();
+    sink_overrides.extend(parse_overrides(&options.sink_overrides)?);
+    let identify_by = IdentifyBy::parse(&config.devices.identify_by)
+        .ok_or(format!("unknown identify_by {}, expected serial or port", config.devices.identify_by))?;
+    let calibration = match config.devices.calibration {
+        None => CalibrationStore::new(),
+        Some(ref path) => CalibrationStore::load(Path::new(path)).map_err(|e| format!("cannot load calibration: {}", e))?,
+    };
+    Ok(Discovery {
+        filter: SourceFilter::new(&config.devices.filter_sources, &config.devices.filter_not_sources, !config.devices.all_buses)?,
+        sink_overrides: sink_overrides,
+        identify_by: identify_by,
+        calibration: calibration,
+        record_consent: config.devices.record_consent.clone(),
+        config: config.clone(),
+    })
+}
+
+
+fn modified(path: &String) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+
+fn run() {
+    let mut verbose: usize = 0;
     let mut filenames: Vec<String> = vec![];
     let mut s2a: f64 = 0.0;
     let mut a2s: f64 = 0.0;
-    let mut filter_sources: String = format!("");
-    let mut filter_not_sources: String = format!("");
+    let mut filter_sources: Vec<String> = vec![];
+    let mut filter_not_sources: Vec<String> = vec![];
+    let mut usb_only = true;
     let mut debug = false;
+    let mut record_dir: String = format!("");
+    let mut record_max_mb: u64 = 1024;
+    let mut record_consent: Vec<String> = vec![];
+    let mut sink_overrides: Vec<String> = vec![];
+    let mut identify_by: String = format!("");
+    let mut calibration_file: String = format!("");
+    let mut hotplug_interval: f64 = -1.0;
+    let mut config_file: String = format!("");
+    let mut listen: String = format!("");
+    let mut snapshot_interval: f64 = 0.25;
+    let mut gui = false;
+    let mut osc_send: String = format!("");
+    let mut osc_listen: String = format!("");
+    let mut event_log: String = format!("");
+    let mut event_log_max_mb: u64 = 100;
+    let mut event_log_keep: usize = 5;
+    let mut trace_dir: String = format!("");
 
     {  // this block limits scope of borrows by ap.refer() method
         let mut ap = ArgumentParser::new();
         ap.set_description("Egloorator");
         ap.refer(&mut verbose)
-            .add_option(&["-v", "--verbose"], StoreTrue,
-            "Be verbose");
+            .add_option(&["-v", "--verbose"], IncrBy(1usize),
+            "Be verbose, -vv for every rms reading");
         ap.refer(&mut filenames).add_option(&["-f", "--filenames"], Collect, "Filenames");
         ap.refer(&mut s2a).add_option(&["-s", "--s2a"], Store, "Silent to Active");
         ap.refer(&mut a2s).add_option(&["-a", "--a2s"], Store, "Active to Silent");
-        ap.refer(&mut filter_sources).add_option(&["-i", "--filter-sources"], Store, "Filter sources");
-        ap.refer(&mut filter_not_sources).add_option(&["-x", "--filter-not-sources"], Store, "Filter sources");
+        ap.refer(&mut config_file).add_option(&["-C", "--config"], Store, "Config file (toml), reloaded on SIGHUP or when it changes");
+        ap.refer(&mut filter_sources).add_option(&["-i", "--filter-sources"], Collect, "Only use sources matching this regex (repeatable)");
+        ap.refer(&mut filter_not_sources).add_option(&["-x", "--filter-not-sources"], Collect, "Skip sources matching this regex (repeatable)");
+        ap.refer(&mut usb_only).add_option(&["--all-buses"], StoreFalse, "Also use non usb sources (bluetooth, built in)");
+        ap.refer(&mut sink_overrides).add_option(&["-k", "--sink"], Collect, "source=sink, override the sink matched to a source");
+        ap.refer(&mut identify_by).add_option(&["--identify-by"], Store, "serial: settings follow the headset, port: settings follow the seat");
+        ap.refer(&mut calibration_file).add_option(&["-c", "--calibration"], Store, "Per headset calibration file (json)");
+        ap.refer(&mut hotplug_interval).add_option(&["--hotplug-interval"], Store, "Seconds between checks for added or removed headsets, 0 to disable");
         ap.refer(&mut debug).add_option(&["-d", "--debug"], StoreTrue, "debug (turn on sine sound)");
+        ap.refer(&mut record_dir).add_option(&["--record-dir"], Store, "Record every conversation into this directory");
+        ap.refer(&mut record_max_mb).add_option(&["--record-max-mb"], Store, "Delete oldest conversation recordings above this size");
+        ap.refer(&mut record_consent).add_option(&["--record-consent"], Collect, "Source (substring) whose wearer consented to being recorded");
+        ap.refer(&mut listen).add_option(&["--listen"], Store, "host:port for the dashboard and the http api, e.g. 0.0.0.0:8080");
+        ap.refer(&mut osc_send).add_option(&["--osc-send"], Store, "host:port to send osc messages about voices and pairs to");
+        ap.refer(&mut osc_listen).add_option(&["--osc-listen"], Store, "host:port to accept osc override commands on");
+        ap.refer(&mut event_log).add_option(&["--event-log"], Store, "Log every transition, connection and error to this file (json lines)");
+        ap.refer(&mut event_log_max_mb).add_option(&["--event-log-max-mb"], Store, "Start a new event log above this size");
+        ap.refer(&mut event_log_keep).add_option(&["--event-log-keep"], Store, "Number of old event logs to keep");
+        ap.refer(&mut trace_dir).add_option(&["--trace-dir"], Store, "Write every rms reading of every headset into this directory, for replay");
+        ap.refer(&mut gui).add_option(&["-g", "--gui"], StoreTrue, "Open the operator window (levels, thresholds, pairs)");
+        ap.refer(&mut snapshot_interval).add_option(&["--snapshot-interval"], Store, "Seconds between level snapshots on the event stream");
         ap.parse_args_or_exit();
     }
 
-    if debug {
-        unsafe {
-            sine_timeout = 0u64;
-        }
-    }
-
-    println!("using level.interval of {}", level_interval);
-    unsafe {
-        println!("using sine timeout of {}", sine_timeout);
-    }
-
-    let source_devices = get_sources(if filter_sources.len() == 0 { None } else { Some(&filter_sources) }, if filter_not_sources.len() == 0 { None } else { Some(&filter_not_sources) });
-    let sources: Vec<String> = match filenames.len() {
-        0 => source_devices.iter().map(|s| format!("pulsesrc device={}", s)).collect(),
-        _ => filenames.iter().map(|f| format!("filesrc location={} ! wavparse", f)).collect(),
+    let options = Options {
+        filter_sources: filter_sources,
+        filter_not_sources: filter_not_sources,
+        usb_only: usb_only,
+        sink_overrides: sink_overrides,
+        identify_by: identify_by,
+        calibration_file: calibration_file,
+        record_consent: record_consent,
+        debug: debug,
     };
-    let mut sinks: Vec<String> =
-        if filenames.len() == 0 {
-            sources.iter().map(|s: &String| format!("pulsesink device={}",
-                                     s.replace("input", "output").replace("mono", "stereo"))).collect()
-        } else {
-            (0..(filenames.len())).map(|i| format!("filesink location=output_{}.wav", i)).collect()
-        };
-    // compile error: map(|s: String| s.replace("input", "output"));
-    println!("{} sources:", sources.len());
-    for source in &sources {
-        println!("{}", source);
-    }
-
-    println!("{} sinks:", sinks.len());
-    for sink in &sinks {
-        println!("{}", sink);
-    }
-
-    fn source_pipelines() {
-
+    let mut config = make_config(&config_file, &options).unwrap_or_else(|e| {
+        println!("cannot load config: {}", e);
+        process::exit(1);
+    });
+    logging::init(&config.logging, verbose).unwrap_or_else(|e| {
+        println!("cannot set up logging: {}", e);
+        process::exit(1);
+    });
+    let mut discovery = make_discovery(&config, &options).unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(2);
+    });
+    if hotplug_interval < 0.0 {
+        hotplug_interval = config.devices.hotplug_interval;
     }
 
-    fn make_level_pipeline(source: &String) -> String {
-        format!("{} ! level interval={} ! fakesink", source, level_interval)
+    info!("using level.interval of {}", config.vad.level_interval);
+    info!("sine while active: {}", config.cues.sine_while_active);
+    info!("filter sources:      {:?}", config.devices.filter_sources);
+    info!("filter not sources:  {:?}", config.devices.filter_not_sources);
+
+    let headsets: Vec<Headset> = match filenames.len() {
+        0 => {
+            let (headsets, warnings) = discovery.headsets(&PulseAudio).unwrap_or_else(|e| {
+                error!("cannot list devices: {}", e);
+                process::exit(1);
+            });
+            for warning in warnings {
+                warn!("{}", warning);
+            }
+            headsets
+        },
+        _ => filenames.iter().enumerate().map(|(i, f)| {
+            discovery.headset(file_headset_id(f),
+                              format!("filesrc location={} ! wavparse", f),
+                              format!("wavenc ! filesink location=output_{}.wav", i))
+        }).collect(),
+    };
+    info!("{} headsets:", headsets.len());
+    for headset in &headsets {
+        info!("{}: {} -> {}", headset.id, headset.source, headset.sink);
     }
 
     gst::init();
@@ -257,41 +314,154 @@ fn main() {
 
     mainloop.spawn();
 
-    let mut handles: Vec<std::thread::JoinHandle<()>> = Vec::new();
     let (tx, rx) = channel();
-
-    for (i, (orig_source, orig_sink)) in sources.iter().zip(sinks.clone()).enumerate() {
-        let source = orig_source.clone();
-        let sink = orig_sink.clone();
-        let tx = tx.clone();
-        let handle = thread::spawn(move || {
-            let level_pipeline_str = make_level_pipeline(&source);
-            let mut level_pipeline = gst::Pipeline::new_from_str(&level_pipeline_str).unwrap();
-            level_pipeline.play();
-            watch_level(i, &source, &sink, &mut level_pipeline, &tx);
+    let running = Arc::new(AtomicBool::new(true));
+    signals::install();
+
+    let status: SharedStatus = Arc::new(Mutex::new(Status::default()));
+    let events = Events::new();
+    let metrics = Metrics::new();
+    let (command_tx, command_rx) = channel();
+    if listen.len() > 0 {
+        control::serve(&listen, status.clone(), command_tx.clone(), events.clone(), metrics.clone()).unwrap_or_else(|e| {
+            error!("cannot listen on {}: {}", listen, e);
+            process::exit(1);
+        });
+        info!("dashboard on http://{}/", listen);
+    }
+    let lighting = Arc::new(Mutex::new(config.lighting.clone()));
+    artnet::run(lighting.clone(), status.clone()).unwrap_or_else(|e| {
+        error!("cannot send art-net: {}", e);
+        process::exit(1);
+    });
+    if osc_send.len() > 0 {
+        osc::send_events(&osc_send, events.subscribe()).unwrap_or_else(|e| {
+            error!("cannot send osc to {}: {}", osc_send, e);
+            process::exit(1);
+        });
+    }
+    if event_log.len() > 0 {
+        let log = EventLog::new(Path::new(&event_log), event_log_max_mb * 1024 * 1024, event_log_keep).unwrap_or_else(|e| {
+            error!("cannot open event log: {}", e);
+            process::exit(1);
+        });
+        eventlog::run(log, events.subscribe());
+    }
+    if osc_listen.len() > 0 {
+        osc::listen(&osc_listen, command_tx.clone()).unwrap_or_else(|e| {
+            error!("cannot listen for osc on {}: {}", osc_listen, e);
+            process::exit(1);
         });
-        handles.push(handle);
     }
 
+    let coordinator_running = running.clone();
+    let coordinator_status = status.clone();
+    let coordinator_events = events.clone();
+    let coordinator_metrics = metrics.clone();
+    let backend: SharedBackend = Arc::new(GstBackend);
+    let coordinator_backend = backend.clone();
+    let hub_tx = tx.clone();
     let coordinator = thread::spawn(move || {
-        let mut hub = Hub::new(&sources, &sinks);
+        let mut hub = Hub::new(hub_tx, coordinator_backend);
+        hub.set_events(coordinator_events);
+        hub.set_metrics(coordinator_metrics);
+        if record_dir.len() > 0 {
+            hub.set_recorder(Recorder::new(Path::new(&record_dir), record_max_mb * 1024 * 1024));
+        }
 
         for msg in rx {
-            println!("sending {:?} to hub", msg);
-            match msg {
-                Message::Update(silence_change) => hub.input(&silence_change),
-                Message::Quit => break,
+            if !hub.handle(msg) {
+                break;
             }
+            hub.publish(&mut coordinator_status.lock().unwrap());
         }
+        hub.shutdown();
+        gst_wait_finalized();
+        coordinator_running.store(false, Ordering::SeqCst);
     });
 
-    coordinator.join().unwrap();
+    // everything but gtk, which wants the main thread
+    let supervisor_running = running.clone();
+    let supervisor_status = status.clone();
+    let supervisor = thread::spawn(move || {
+        let running = supervisor_running;
+        let status = supervisor_status;
+        let mut watchers = Watchers::new(backend, tx.clone(), status.clone(), metrics);
+        if trace_dir.len() > 0 {
+            watchers.set_traces(Path::new(&trace_dir));
+        }
+        let mut device_watcher = DeviceWatcher::new();
+        watchers.apply(device_watcher.update(headsets), &config);
+
+        // files do not come and go, only poll for live devices
+        let hotplug = filenames.len() == 0 && hotplug_interval > 0.0;
+        let interval = Duration::from_millis((hotplug_interval.max(0.0) * 1000.0) as u64);
+        let mut last_poll = Instant::now();
+        let snapshot_every = Duration::from_millis((snapshot_interval * 1000.0) as u64);
+        let mut last_snapshot = Instant::now();
+        let mut config_modified = modified(&config_file);
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+            if signals::shutdown_requested() {
+                info!("shutting down");
+                tx.send(Message::Quit).ok();
+                break;
+            }
+
+            let reload = signals::reload_requested() || (config_file.len() > 0 && modified(&config_file) != config_modified);
+            if reload && config_file.len() > 0 {
+                config_modified = modified(&config_file);
+                match make_config(&config_file, &options).and_then(|c| make_discovery(&c, &options).map(|d| (c, d))) {
+                    Ok((new_config, new_discovery)) => {
+                        info!("reloaded {}", config_file);
+                        if new_config.vad.level_interval != config.vad.level_interval {
+                            warn!("vad.level_interval only applies to headsets added from now on");
+                        }
+                        config = new_config;
+                        discovery = new_discovery;
+                        *lighting.lock().unwrap() = config.lighting.clone();
+                        watchers.reconfigure(device_watcher.present(), &discovery, &config);
+                    },
+                    Err(e) => error!("cannot reload {}, keeping the old config: {}", config_file, e),
+                }
+            }
+
+            if hotplug && last_poll.elapsed() >= interval {
+                last_poll = Instant::now();
+                match discovery.headsets(&PulseAudio) {
+                    Ok((headsets, _)) => watchers.apply(device_watcher.update(headsets), &config),
+                    Err(e) => warn!("hotplug: cannot list devices: {}", e),
+                }
+            }
 
-    for handle in handles {
-        handle.join().unwrap();
+            if last_snapshot.elapsed() >= snapshot_every {
+                last_snapshot = Instant::now();
+                let voices = status.lock().unwrap().voices.iter()
+                    .filter_map(|(voice, v)| v.rms.map(|rms| (*voice, Level { rms: rms, silent: v.silent })))
+                    .collect();
+                events.publish(Event::Levels { voices: voices });
+            }
+
+            while let Ok(command) = command_rx.try_recv() {
+                watchers.command(command, device_watcher.present(), &mut discovery, &config);
+            }
+        }
+
+        coordinator.join().unwrap();
+
+        watchers.stop_all();
+    });
+
+    if gui {
+        match gui::run(status.clone(), command_tx, running.clone()) {
+            Ok(()) => signals::request_shutdown(), // closing the window stops egloorator
+            Err(e) => error!("{}, running without the gui", e),
+        }
     }
 
-    println!("done");
+    supervisor.join().unwrap();
+
+    info!("done");
 
     mainloop.quit();
 }
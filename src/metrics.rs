@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use calibration::Calibration;
+use hub::Voice;
+use sessions::Sessions;
+
+
+// Prometheus metrics, served as text at /metrics of the control api. Per headset
+// (labels voice and headset id):
+//
+//   egloo_rms_db, egloo_active, egloo_s2a_db, egloo_a2s_db           gauges
+//   egloo_transitions_total, egloo_connects_total,
+//   egloo_disconnects_total, egloo_errors_total, egloo_restarts_total counters
+//
+// and over all headsets the histograms egloo_conversation_duration_seconds and
+// egloo_wait_seconds, the time from becoming active to being paired, counted like the
+// report does (see sessions.rs).
+
+const CONVERSATION_BUCKETS: &[f64] = &[10f64, 30f64, 60f64, 120f64, 300f64, 600f64, 900f64, 1800f64, 3600f64];
+const WAIT_BUCKETS: &[f64] = &[1f64, 5f64, 10f64, 30f64, 60f64, 120f64, 300f64, 600f64];
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct HeadsetMetrics {
+    id: String,
+    rms: Option<f64>,
+    active: bool,
+    thresholds: Option<Calibration>,
+    transitions: u64,
+    connects: u64,
+    disconnects: u64,
+    errors: u64,
+    restarts: u64,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>, // per bucket, not cumulative
+    sum: f64,
+    count: u64,
+}
+
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds: bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0f64,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        match self.bounds.iter().position(|b| value <= *b) {
+            Some(i) => self.counts[i] += 1,
+            None => {}
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        *out += &format!("# HELP {} {}\n# TYPE {} histogram\n", name, help, name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            *out += &format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative);
+        }
+        *out += &format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, self.count);
+        *out += &format!("{}_sum {}\n{}_count {}\n", name, self.sum, name, self.count);
+    }
+}
+
+
+struct Inner {
+    headsets: BTreeMap<Voice, HeadsetMetrics>,
+    sessions: Sessions<Voice, Instant>,
+    conversation_duration: Histogram,
+    wait: Histogram,
+}
+
+
+impl Inner {
+    fn headset(&mut self, voice: Voice, id: &String) -> &mut HeadsetMetrics {
+        let headset = self.headsets.entry(voice).or_insert(HeadsetMetrics::default());
+        headset.id = id.clone();
+        headset
+    }
+}
+
+
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+
+pub type SharedMetrics = Arc<Metrics>;
+
+
+fn escape(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+
+fn seconds(from: Instant, to: Instant) -> f64 {
+    let elapsed = to.duration_since(from);
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+}
+
+
+impl Metrics {
+    pub fn new() -> SharedMetrics {
+        Arc::new(Metrics {
+            inner: Mutex::new(Inner {
+                headsets: BTreeMap::new(),
+                sessions: Sessions::new(),
+                conversation_duration: Histogram::new(CONVERSATION_BUCKETS),
+                wait: Histogram::new(WAIT_BUCKETS),
+            }),
+        })
+    }
+
+    // every level message, with the thresholds it was judged by
+    pub fn level(&self, voice: Voice, id: &String, rms: f64, calibration: &Calibration) {
+        let mut inner = self.inner.lock().unwrap();
+        let headset = inner.headset(voice, id);
+        headset.rms = Some(rms);
+        headset.thresholds = Some(*calibration);
+    }
+
+    pub fn transition(&self, voice: Voice, id: &String, silent: bool) {
+        self.transition_at(voice, id, silent, Instant::now());
+    }
+
+    fn transition_at(&self, voice: Voice, id: &String, silent: bool, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        {
+            let headset = inner.headset(voice, id);
+            headset.transitions += 1;
+            headset.active = !silent;
+        }
+        if silent {
+            inner.sessions.silent(&voice);
+        } else {
+            inner.sessions.active(&voice, now);
+        }
+    }
+
+    pub fn connect(&self, voices: (Voice, Voice), ids: &(String, String)) {
+        self.connect_at(voices, ids, Instant::now());
+    }
+
+    fn connect_at(&self, (one, two): (Voice, Voice), ids: &(String, String), now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.headset(one, &ids.0).connects += 1;
+        inner.headset(two, &ids.1).connects += 1;
+        for (_, since) in inner.sessions.connect(&one, &two, now) {
+            inner.wait.observe(seconds(since, now));
+        }
+    }
+
+    pub fn disconnect(&self, voices: (Voice, Voice), ids: &(String, String)) {
+        self.disconnect_at(voices, ids, Instant::now());
+    }
+
+    fn disconnect_at(&self, (one, two): (Voice, Voice), ids: &(String, String), now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.headset(one, &ids.0).disconnects += 1;
+        inner.headset(two, &ids.1).disconnects += 1;
+        match inner.sessions.disconnect(&one, &two) {
+            Some(start) => inner.conversation_duration.observe(seconds(start, now)),
+            None => {}
+        }
+    }
+
+    pub fn error(&self, voice: Voice, id: &String) {
+        self.inner.lock().unwrap().headset(voice, id).errors += 1;
+    }
+
+    pub fn restart(&self, voice: Voice, id: &String) {
+        self.inner.lock().unwrap().headset(voice, id).restarts += 1;
+    }
+
+    // the prometheus text format, version 0.0.4
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = format!("");
+        let families: &[(&str, &str, &str, fn(&HeadsetMetrics) -> Option<f64>)] = &[
+            ("egloo_rms_db", "gauge", "Last rms level in dB", |h| h.rms),
+            ("egloo_active", "gauge", "1 while the headset is active, 0 while silent", |h| Some(if h.active { 1f64 } else { 0f64 })),
+            ("egloo_s2a_db", "gauge", "Silent to active threshold in dB", |h| h.thresholds.map(|t| t.s2a)),
+            ("egloo_a2s_db", "gauge", "Active to silent threshold in dB", |h| h.thresholds.map(|t| t.a2s)),
+            ("egloo_transitions_total", "counter", "Changes between silent and active", |h| Some(h.transitions as f64)),
+            ("egloo_connects_total", "counter", "Conversations started", |h| Some(h.connects as f64)),
+            ("egloo_disconnects_total", "counter", "Conversations ended", |h| Some(h.disconnects as f64)),
+            ("egloo_errors_total", "counter", "Level pipeline and route errors", |h| Some(h.errors as f64)),
+            ("egloo_restarts_total", "counter", "Level pipelines working again after a restart", |h| Some(h.restarts as f64)),
+        ];
+        for &(name, kind, help, value) in families {
+            out += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+            for (voice, headset) in &inner.headsets {
+                match value(headset) {
+                    Some(v) => out += &format!("{}{{voice=\"{}\",headset=\"{}\"}} {}\n", name, voice, escape(&headset.id), v),
+                    None => {}
+                }
+            }
+        }
+        inner.conversation_duration.render(&mut out, "egloo_conversation_duration_seconds", "Length of conversations");
+        inner.wait.render(&mut out, "egloo_wait_seconds", "Time from becoming active to being paired");
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use calibration::Calibration;
+    use super::Metrics;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let ids = (format!("a"), format!("b\"1"));
+        let t = Instant::now();
+        metrics.level(1, &ids.0, -41.5, &Calibration { s2a: -45f64, a2s: -47f64 });
+        metrics.transition_at(1, &ids.0, false, t);
+        metrics.transition_at(2, &ids.1, false, t + Duration::from_secs(20));
+        metrics.connect_at((2, 1), &ids, t + Duration::from_secs(20));
+        metrics.disconnect_at((1, 2), &ids, t + Duration::from_secs(140));
+        metrics.error(2, &ids.1);
+        let text = metrics.render();
+        assert!(text.contains("# TYPE egloo_rms_db gauge\negloo_rms_db{voice=\"1\",headset=\"a\"} -41.5\n# HELP"));
+        assert!(text.contains("egloo_active{voice=\"1\",headset=\"a\"} 1\n"));
+        assert!(text.contains("egloo_s2a_db{voice=\"1\",headset=\"a\"} -45\n"));
+        assert!(text.contains("egloo_connects_total{voice=\"2\",headset=\"b\\\"1\"} 1\n"));
+        assert!(text.contains("egloo_errors_total{voice=\"2\",headset=\"b\\\"1\"} 1\n"));
+        assert!(text.contains("egloo_conversation_duration_seconds_bucket{le=\"60\"} 0\n\
+                               egloo_conversation_duration_seconds_bucket{le=\"120\"} 1\n"));
+        assert!(text.contains("egloo_conversation_duration_seconds_sum 120\negloo_conversation_duration_seconds_count 1\n"));
+        // one waited 20s, the other was paired right away
+        assert!(text.contains("egloo_wait_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(text.contains("egloo_wait_seconds_bucket{le=\"30\"} 2\n"));
+        assert!(text.contains("egloo_wait_seconds_sum 20\n"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::net::UdpSocket;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use calibration::Calibration;
+use control::Command;
+use events::{Event, Stamped};
+use hub::Voice;
+
+
+// OSC 1.0 over udp, for show control software (QLab, TouchDesigner, ...).
+//
+// sent, one message per event:
+//   /egloo/voice/3/active 1           became active (0: silent)
+//   /egloo/voice/3/rms -31.2          with every level snapshot
+//   /egloo/pair/connect 1 4
+//   /egloo/pair/disconnect 1 4
+//
+// accepted, the same overrides as the control api. Numbers may be ints or floats.
+//   /egloo/pair/connect 1 4
+//   /egloo/pair/disconnect 1
+//   /egloo/voice/3/mute 1             (0 to unmute)
+//   /egloo/voice/3/offline 1          (0 to bring it back)
+//   /egloo/voice/3/thresholds -50 -52 s2a a2s
+//   /egloo/voice/3/save               thresholds into the calibration file
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub addr: String,
+    pub args: Vec<Arg>,
+}
+
+
+impl OscMessage {
+    pub fn new(addr: String, args: Vec<Arg>) -> OscMessage {
+        OscMessage {
+            addr: addr,
+            args: args,
+        }
+    }
+}
+
+
+// strings are nul terminated and padded to 4 bytes
+fn push_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+}
+
+
+pub fn encode(message: &OscMessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    push_string(&mut buf, &message.addr);
+    let tags = message.args.iter().map(|a| match *a {
+        Arg::Int(_) => 'i',
+        Arg::Float(_) => 'f',
+        Arg::Str(_) => 's',
+    }).collect::<String>();
+    push_string(&mut buf, &format!(",{}", tags));
+    for arg in &message.args {
+        match *arg {
+            Arg::Int(i) => push_u32(&mut buf, i as u32),
+            Arg::Float(f) => push_u32(&mut buf, f.to_bits()),
+            Arg::Str(ref s) => push_string(&mut buf, s),
+        }
+    }
+    buf
+}
+
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+
+impl<'a> Reader<'a> {
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.buf[self.pos..];
+        let len = rest.iter().position(|b| *b == 0).ok_or(format!("unterminated string"))?;
+        let s = String::from_utf8(rest[..len].to_vec()).map_err(|_| format!("string is not utf8"))?;
+        // padded to a multiple of 4
+        let end = self.pos + (len + 4) / 4 * 4;
+        if end > self.buf.len() {
+            return Err(format!("message too short"));
+        }
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        if self.pos + 4 > self.buf.len() {
+            return Err(format!("message too short"));
+        }
+        let b = &self.buf[self.pos..self.pos + 4];
+        self.pos += 4;
+        Ok((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+    }
+}
+
+
+pub fn decode(buf: &[u8]) -> Result<OscMessage, String> {
+    let mut reader = Reader { buf: buf, pos: 0 };
+    let addr = reader.string()?;
+    if !addr.starts_with("/") {
+        return Err(format!("not an osc message (bundles are not supported)"));
+    }
+    // a missing type tag string means no arguments
+    let tags = if reader.pos < buf.len() { reader.string()? } else { format!(",") };
+    if !tags.starts_with(",") {
+        return Err(format!("bad type tags `{}`", tags));
+    }
+    let mut args = Vec::new();
+    for tag in tags[1..].chars() {
+        args.push(match tag {
+            'i' => Arg::Int(reader.u32()? as i32),
+            'f' => Arg::Float(f32::from_bits(reader.u32()?)),
+            's' => Arg::Str(reader.string()?),
+            _ => return Err(format!("unsupported type tag `{}`", tag)),
+        });
+    }
+    Ok(OscMessage::new(addr, args))
+}
+
+
+pub fn event_messages(event: &Event) -> Vec<OscMessage> {
+    match *event {
+        Event::SilenceChange { voice, silent, .. } =>
+            vec![OscMessage::new(format!("/egloo/voice/{}/active", voice), vec![Arg::Int(if silent { 0 } else { 1 })])],
+        Event::Connect { voices: (one, two), .. } =>
+            vec![OscMessage::new(format!("/egloo/pair/connect"), vec![Arg::Int(one as i32), Arg::Int(two as i32)])],
+        Event::Disconnect { voices: (one, two), .. } =>
+            vec![OscMessage::new(format!("/egloo/pair/disconnect"), vec![Arg::Int(one as i32), Arg::Int(two as i32)])],
+        Event::Levels { ref voices } => voices.iter().map(|(voice, level)| {
+            OscMessage::new(format!("/egloo/voice/{}/rms", voice), vec![Arg::Float(level.rms as f32)])
+        }).collect(),
+        Event::Error { .. } | Event::Restart { .. } => vec![],
+    }
+}
+
+
+fn number(args: &Vec<Arg>, i: usize) -> Result<f64, String> {
+    match args.get(i) {
+        Some(&Arg::Int(v)) => Ok(v as f64),
+        Some(&Arg::Float(v)) => Ok(v as f64),
+        _ => Err(format!("argument {} should be a number", i + 1)),
+    }
+}
+
+
+fn voice(args: &Vec<Arg>, i: usize) -> Result<Voice, String> {
+    let v = number(args, i)?;
+    if v < 0f64 {
+        return Err(format!("bad voice {}", v));
+    }
+    Ok(v as Voice)
+}
+
+
+pub fn parse_command(message: &OscMessage) -> Result<Command, String> {
+    let args = &message.args;
+    let parts = message.addr.split('/').collect::<Vec<&str>>();
+    match &parts[..] {
+        &["", "egloo", "pair", "connect"] => Ok(Command::Connect(voice(args, 0)?, voice(args, 1)?)),
+        &["", "egloo", "pair", "disconnect"] => Ok(Command::Disconnect(voice(args, 0)?)),
+        &["", "egloo", "voice", n, what] => {
+            let v = n.parse::<Voice>().map_err(|_| format!("bad voice `{}`", n))?;
+            match what {
+                "mute" => Ok(Command::Mute(v, number(args, 0)? != 0f64)),
+                "offline" => Ok(Command::Offline(v, number(args, 0)? != 0f64)),
+                "thresholds" => {
+                    let calibration = Calibration { s2a: number(args, 0)?, a2s: number(args, 1)? };
+                    if calibration.s2a < calibration.a2s {
+                        return Err(format!("s2a ({}) must not be below a2s ({})", calibration.s2a, calibration.a2s));
+                    }
+                    Ok(Command::Thresholds(v, calibration))
+                },
+                "save" => Ok(Command::SaveCalibration(v)),
+                _ => Err(format!("unknown command {}", message.addr)),
+            }
+        },
+        _ => Err(format!("unknown command {}", message.addr)),
+    }
+}
+
+
+// Send every event to target (host:port) from a thread of its own
+pub fn send_events(target: &str, events: Receiver<Stamped>) -> Result<(), String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket.connect(target).map_err(|e| format!("{}: {}", target, e))?;
+    thread::spawn(move || {
+        for stamped in events {
+            for message in event_messages(&stamped.event) {
+                // nobody listening is fine, the show may not have started yet
+                socket.send(&encode(&message)).ok();
+            }
+        }
+    });
+    Ok(())
+}
+
+
+// Accept commands on addr (host:port) from a thread of its own
+pub fn listen(addr: &str, commands: Sender<Command>) -> Result<(), String> {
+    let socket = UdpSocket::bind(addr).map_err(|e| format!("{}: {}", addr, e))?;
+    thread::spawn(move || {
+        let mut buf = [0u8; 1536];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("osc: {}", e);
+                    continue;
+                }
+            };
+            match decode(&buf[..len]).and_then(|m| parse_command(&m)) {
+                Ok(command) => {
+                    if commands.send(command).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => warn!("osc: from {}: {}", from, e),
+            }
+        }
+    });
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use calibration::Calibration;
+    use control::Command;
+    use events::{Event, Events};
+    use super::{decode, encode, listen, parse_command, send_events, Arg, OscMessage};
+
+    fn message(addr: &str, args: Vec<Arg>) -> OscMessage {
+        OscMessage::new(String::from(addr), args)
+    }
+
+    #[test]
+    fn test_encode() {
+        let bytes = encode(&message("/egloo/voice/3/active", vec![Arg::Int(1)]));
+        assert_eq!(&bytes[..], &b"/egloo/voice/3/active\0\0\0,i\0\0\0\0\0\x01"[..]);
+        let m = message("/egloo/voice/3/thresholds", vec![Arg::Float(-50.5), Arg::Int(-52), Arg::Str(format!("x"))]);
+        assert_eq!(decode(&encode(&m)), Ok(m));
+        assert!(decode(b"#bundle\0").is_err());
+        assert_eq!(decode(b"/a\0\0,ss\0x\0"), Err(format!("message too short")));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(&message("/egloo/pair/connect", vec![Arg::Int(1), Arg::Float(4.0)])), Ok(Command::Connect(1, 4)));
+        assert_eq!(parse_command(&message("/egloo/pair/disconnect", vec![Arg::Int(2)])), Ok(Command::Disconnect(2)));
+        assert_eq!(parse_command(&message("/egloo/voice/3/mute", vec![Arg::Int(0)])), Ok(Command::Mute(3, false)));
+        assert_eq!(parse_command(&message("/egloo/voice/3/thresholds", vec![Arg::Float(-50.0), Arg::Int(-52)])),
+                   Ok(Command::Thresholds(3, Calibration { s2a: -50f64, a2s: -52f64 })));
+        assert_eq!(parse_command(&message("/egloo/voice/3/save", vec![])), Ok(Command::SaveCalibration(3)));
+        assert!(parse_command(&message("/egloo/voice/x/mute", vec![Arg::Int(1)])).is_err());
+        assert!(parse_command(&message("/egloo/pair/connect", vec![Arg::Int(1)])).is_err());
+        assert!(parse_command(&message("/egloo/voice/3/explode", vec![])).is_err());
+    }
+
+    #[test]
+    fn test_udp() {
+        // a show control program listening for us
+        let show = UdpSocket::bind("127.0.0.1:0").unwrap();
+        show.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let events = Events::new();
+        send_events(&show.local_addr().unwrap().to_string(), events.subscribe()).unwrap();
+        events.publish(Event::SilenceChange { voice: 3, id: format!("a"), silent: false, rms: -40.0, s2a: -45.0, a2s: -47.0 });
+        events.publish(Event::Connect { voices: (1, 4), ids: (format!("b"), format!("c")) });
+        let mut buf = [0u8; 1536];
+        let len = show.recv(&mut buf).unwrap();
+        assert_eq!(decode(&buf[..len]), Ok(message("/egloo/voice/3/active", vec![Arg::Int(1)])));
+        let len = show.recv(&mut buf).unwrap();
+        assert_eq!(decode(&buf[..len]), Ok(message("/egloo/pair/connect", vec![Arg::Int(1), Arg::Int(4)])));
+
+        // and sending us an override
+        let (tx, rx) = channel();
+        let probe = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = probe.local_addr().unwrap().to_string();
+        drop(probe);
+        listen(&addr, tx).unwrap();
+        show.send_to(&encode(&message("/egloo/voice/2/offline", vec![Arg::Int(1)])), &addr).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(Command::Offline(2, true)));
+    }
+}
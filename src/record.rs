@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{stdout, stderr};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate gst;
+use gst::ElementT;
+
+use argparse::{ArgumentParser, Store, StoreFalse, Collect};
+use chrono::Local;
+use serde_json;
+
+use calibration::CalibrationStore;
+use config::Config;
+use headset::{headset_ids, HeadsetId, IdentifyBy};
+use levels::get_short_name;
+use devices::PulseAudio;
+use signals;
+use sources::{get_sources, SourceFilter};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Wav,
+    Ogg,
+}
+
+
+impl Format {
+    fn parse(s: &str) -> Option<Format> {
+        match s {
+            "wav" => Some(Format::Wav),
+            "ogg" => Some(Format::Ogg),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match *self {
+            Format::Wav => "wav",
+            Format::Ogg => "ogg",
+        }
+    }
+
+    fn last_stage(&self) -> &'static str {
+        match *self {
+            Format::Wav => "wavenc",
+            Format::Ogg => "audioconvert ! vorbisenc quality=0.8 ! oggmux",
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize)]
+struct DeviceMetadata {
+    id: HeadsetId,
+    source: String,
+    description: String,
+    channels: u32,
+    sample_rate: u32,
+    name: String,
+    file: String,
+    s2a: f64,
+    a2s: f64,
+    amplification: f64,
+}
+
+
+#[derive(Debug, Serialize)]
+struct SessionMetadata {
+    started: String,
+    format: String,
+    devices: Vec<DeviceMetadata>,
+}
+
+
+// pick a file name for every source, appending _N on collisions
+fn make_filenames(dirname: &Path, names: &Vec<String>, extension: &str) -> Vec<PathBuf>
+{
+    let mut used_names = HashSet::new();
+    let mut out = Vec::new();
+    for name in names {
+        let mut filename = dirname.join(format!("{}.{}", name, extension));
+        let mut ind = 0;
+        while used_names.contains(&filename) {
+            filename = dirname.join(format!("{}_{}.{}", name, ind, extension));
+            ind += 1;
+        }
+        used_names.insert(filename.clone());
+        out.push(filename);
+    }
+    out
+}
+
+
+fn write_metadata(dirname: &Path, metadata: &SessionMetadata) -> Result<(), String>
+{
+    let file = fs::File::create(dirname.join("metadata.json")).map_err(|e| e.to_string())?;
+    serde_json::to_writer_pretty(file, metadata).map_err(|e| e.to_string())
+}
+
+
+pub fn main(args: Vec<String>)
+{
+    let mut filter_sources: Vec<String> = vec![];
+    let mut filter_not_sources: Vec<String> = vec![];
+    let mut usb_only = true;
+    let mut dirname_suffix: String = format!("");
+    let mut output_dir: String = format!(".");
+    let mut format: String = format!("ogg");
+    let mut duration: f64 = 0.0;
+    let mut identify_by: String = format!("");
+    let mut calibration_file: String = format!("");
+    let mut config_file: String = format!("");
+
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Record all headsets, one file per source");
+        ap.refer(&mut filter_sources).add_option(&["-i", "--filter-sources"], Collect, "Only record sources matching this regex (repeatable)");
+        ap.refer(&mut filter_not_sources).add_option(&["-x", "--filter-not-sources"], Collect, "Skip sources matching this regex (repeatable)");
+        ap.refer(&mut usb_only).add_option(&["--all-buses"], StoreFalse, "Also record non usb sources (bluetooth, built in)");
+        ap.refer(&mut dirname_suffix).required().add_option(&["-d", "--dirname-suffix"], Store, "Session directory suffix");
+        ap.refer(&mut output_dir).add_option(&["-o", "--output-dir"], Store, "Directory to create the session directory in");
+        ap.refer(&mut format).add_option(&["--format"], Store, "wav or ogg");
+        ap.refer(&mut duration).add_option(&["-t", "--duration"], Store, "Seconds to record, 0 to record until stopped");
+        ap.refer(&mut identify_by).add_option(&["--identify-by"], Store, "serial or port, how headset ids are derived");
+        ap.refer(&mut calibration_file).add_option(&["-c", "--calibration"], Store, "Per headset calibration file (json)");
+        ap.refer(&mut config_file).add_option(&["-C", "--config"], Store, "Take device and amplification settings from this config file");
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {},
+            Err(x) => process::exit(x),
+        }
+    }
+
+    let format = match Format::parse(&format) {
+        Some(f) => f,
+        None => {
+            println!("unknown format {}, expected wav or ogg", format);
+            process::exit(2);
+        }
+    };
+
+    // the command line adds to the config file, like for run
+    let mut config = match config_file.len() {
+        0 => Config::default(),
+        _ => Config::load(Path::new(&config_file)).unwrap_or_else(|e| {
+            println!("cannot load config: {}", e);
+            process::exit(1);
+        }),
+    };
+    config.devices.filter_sources.extend(filter_sources);
+    config.devices.filter_not_sources.extend(filter_not_sources);
+    config.devices.all_buses = config.devices.all_buses || !usb_only;
+    if identify_by.len() > 0 {
+        config.devices.identify_by = identify_by;
+    }
+    if calibration_file.len() > 0 {
+        config.devices.calibration = Some(calibration_file);
+    }
+
+    let identify_by = IdentifyBy::parse(&config.devices.identify_by).unwrap_or_else(|| {
+        println!("unknown identify_by {}, expected serial or port", config.devices.identify_by);
+        process::exit(2);
+    });
+    let calibration = match config.devices.calibration {
+        None => CalibrationStore::new(),
+        Some(ref path) => CalibrationStore::load(Path::new(path)).unwrap_or_else(|e| {
+            println!("cannot load calibration: {}", e);
+            process::exit(1);
+        }),
+    };
+
+    let started = Local::now();
+    let dirname = Path::new(&output_dir).join(format!("{}-{}", started.format("%Y%m%d-%H%M"), dirname_suffix));
+    if dirname.exists() {
+        println!("{} already exists", dirname.display());
+        process::exit(1);
+    }
+    fs::create_dir_all(&dirname).unwrap();
+
+    let filter = SourceFilter::new(&config.devices.filter_sources, &config.devices.filter_not_sources, !config.devices.all_buses).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(2);
+    });
+    let sources = get_sources(&PulseAudio, &filter)
+        .unwrap_or_else(|e| {
+            println!("cannot list sources: {}", e);
+            process::exit(1);
+        });
+    println!("#sources {}", sources.len());
+
+    let names = sources.iter().map(|s| get_short_name(&s.name)).collect::<Vec<String>>();
+    let filenames = make_filenames(&dirname, &names, format.extension());
+    let ids = headset_ids(&sources, identify_by);
+
+    let mut devices = Vec::new();
+    let mut pipelines = Vec::new();
+    for (((source, name), filename), id) in sources.iter().zip(names).zip(filenames).zip(ids) {
+        // calibration tables are keyed by the level pipeline source string
+        let source_str = format!("pulsesrc device={}", source.name);
+        let levels = calibration.get(&id, &source_str);
+        let amplification = config.amplification(&id, &levels);
+        let pipeline_str = format!("{} ! {} ! filesink location=\"{}\"", source_str, format.last_stage(), filename.display());
+        println!("{}", pipeline_str);
+        let mut pipeline = gst::Pipeline::new_from_str(&pipeline_str).unwrap();
+        pipeline.play();
+        pipelines.push(pipeline);
+        devices.push(DeviceMetadata {
+            id: id,
+            source: source.name.clone(),
+            description: source.description.clone(),
+            channels: source.channels,
+            sample_rate: source.sample_rate,
+            name: name,
+            file: String::from(filename.file_name().unwrap().to_string_lossy()),
+            s2a: levels.s2a,
+            a2s: levels.a2s,
+            amplification: amplification,
+        });
+    }
+
+    write_metadata(&dirname, &SessionMetadata {
+        started: started.to_rfc3339(),
+        format: String::from(format.extension()),
+        devices: devices,
+    }).unwrap();
+
+    signals::install();
+    record(&mut pipelines, duration);
+    println!("recorded to {}", dirname.display());
+}
+
+
+// Wait for the pipelines to finish, or send them an EOS after duration seconds or on
+// ctrl-c so the muxers write valid headers.
+fn record(pipelines: &mut Vec<gst::Pipeline>, duration: f64)
+{
+    let receivers = pipelines.iter_mut()
+        .map(|p| p.bus().expect("Couldn't get bus from pipeline").receiver())
+        .collect::<Vec<_>>();
+    let mut done = vec![false; pipelines.len()];
+    let start = Instant::now();
+    let mut eos_sent = false;
+
+    while done.iter().any(|d| !d) {
+        for (i, receiver) in receivers.iter().enumerate() {
+            while let Ok(message) = receiver.try_recv() {
+                match message.parse() {
+                    gst::Message::ErrorParsed{ref error, ..} => {
+                        println!("error from element `{}`: {}", message.src_name(), error.message());
+                        done[i] = true;
+                    }
+                    gst::Message::Eos(_) => {
+                        done[i] = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let timed_out = duration > 0.0 && start.elapsed() >= Duration::from_millis((duration * 1000.0) as u64);
+        if !eos_sent && (timed_out || signals::shutdown_requested()) {
+            for pipeline in pipelines.iter_mut() {
+                pipeline.send_event(gst::Event::new_eos());
+            }
+            eos_sent = true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    for pipeline in pipelines.iter_mut() {
+        pipeline.set_null_state();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::make_filenames;
+
+    #[test]
+    fn test_filename_collisions() {
+        let names = vec![format!("ms-lx-3000"), format!("generic"), format!("ms-lx-3000"), format!("ms-lx-3000")];
+        let filenames = make_filenames(Path::new("session"), &names, "wav");
+        assert_eq!(filenames, vec![
+            Path::new("session/ms-lx-3000.wav").to_path_buf(),
+            Path::new("session/generic.wav").to_path_buf(),
+            Path::new("session/ms-lx-3000_0.wav").to_path_buf(),
+            Path::new("session/ms-lx-3000_1.wav").to_path_buf(),
+        ]);
+    }
+}
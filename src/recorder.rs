@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+extern crate gst;
+use gst::ElementT;
+
+use chrono::Local;
+
+use gst_helpers::gst_finalize_pipeline_later;
+use headset::{Headset, HeadsetId};
+use hub::Voice;
+use sessions::pair;
+
+
+// Records both sides of a conversation into a two channel wav file, one file per
+// Connect .. Disconnect. Only headsets whose wearer gave consent are recorded, and both
+// sides need to have consented.
+pub struct Recorder {
+    dirname: PathBuf,
+    max_bytes: u64,
+    recordings: HashMap<(Voice, Voice), (PathBuf, gst::Pipeline)>,
+    // files still being written, including ones finalizing after stop, retention skips them
+    writing: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+
+fn make_recording_pipeline(source_one: &String, source_two: &String, filename: &Path) -> String {
+    format!("interleave name=rec ! audioconvert ! wavenc ! filesink location=\"{}\" \
+             {} ! audioconvert ! audio/x-raw,channels=1 ! rec.sink_0 \
+             {} ! audioconvert ! audio/x-raw,channels=1 ! rec.sink_1",
+            filename.display(), source_one, source_two)
+}
+
+
+// headset ids contain usb port paths, keep them usable as file names
+fn file_safe(id: &HeadsetId) -> String {
+    id.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' }).collect()
+}
+
+
+// only files named like start() names them, %Y%m%d-%H%M%S-<id>-<id>.wav, anything else
+// in the directory is not ours to remove
+fn is_recording(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    if !name.ends_with(".wav") {
+        return false;
+    }
+    let stem = &name[..name.len() - 4];
+    let b = stem.as_bytes();
+    let digits = |r: &[u8]| r.iter().all(|c| c.is_ascii_digit());
+    b.len() > 16 && digits(&b[0..8]) && b[8] == b'-' && digits(&b[9..15]) && b[15] == b'-'
+        && stem[16..].contains('-')
+        && stem[16..].chars().all(|c| c.is_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+
+// oldest files first until the total is below max_bytes
+fn files_to_prune(mut files: Vec<(PathBuf, u64, SystemTime)>, max_bytes: u64) -> Vec<PathBuf>
+{
+    files.sort_by(|a, b| a.2.cmp(&b.2));
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+    let mut out = Vec::new();
+    for (path, size, _) in files {
+        if total <= max_bytes {
+            break;
+        }
+        total -= size;
+        out.push(path);
+    }
+    out
+}
+
+
+fn prune(dirname: &Path, max_bytes: u64, writing: &HashSet<PathBuf>)
+{
+    let entries = match fs::read_dir(dirname) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("cannot list {}: {}", dirname.display(), e);
+            return;
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !is_recording(&path) || writing.contains(&path) {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) => files.push((path, metadata.len(), metadata.modified().unwrap_or(SystemTime::now()))),
+            Err(_) => {},
+        }
+    }
+    for path in files_to_prune(files, max_bytes) {
+        info!("retention: removing {}", path.display());
+        fs::remove_file(&path).ok();
+    }
+}
+
+
+impl Recorder {
+    pub fn new(dirname: &Path, max_bytes: u64) -> Recorder
+    {
+        fs::create_dir_all(dirname).unwrap();
+        Recorder {
+            dirname: dirname.to_path_buf(),
+            max_bytes: max_bytes,
+            recordings: HashMap::new(),
+            writing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn start(&mut self, one: Voice, two: Voice, headset_one: &Headset, headset_two: &Headset)
+    {
+        if !headset_one.consent || !headset_two.consent {
+            return;
+        }
+        let filename = self.dirname.join(format!("{}-{}-{}.wav", Local::now().format("%Y%m%d-%H%M%S"),
+                                                 file_safe(&headset_one.id), file_safe(&headset_two.id)));
+        info!("recording conversation {} <-> {} to {}", headset_one.id, headset_two.id, filename.display());
+        let s = make_recording_pipeline(&headset_one.source, &headset_two.source, &filename);
+        match gst::Pipeline::new_from_str(&*s) {
+            Ok(mut pipe) => {
+                pipe.play();
+                self.writing.lock().unwrap().insert(filename.clone());
+                self.recordings.insert(pair(&one, &two), (filename, pipe));
+            },
+            Err(e) => error!("cannot record conversation: {}", e.message()),
+        }
+    }
+
+    pub fn stop(&mut self, one: Voice, two: Voice)
+    {
+        match self.recordings.remove(&pair(&one, &two)) {
+            Some((filename, pipe)) => {
+                let dirname = self.dirname.clone();
+                let max_bytes = self.max_bytes;
+                let writing = self.writing.clone();
+                gst_finalize_pipeline_later(pipe, move || {
+                    let mut writing = writing.lock().unwrap();
+                    writing.remove(&filename);
+                    prune(&dirname, max_bytes, &writing);
+                });
+            },
+            None => {
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{files_to_prune, is_recording, prune};
+
+    #[test]
+    fn test_prune_oldest_first() {
+        let t = |s| UNIX_EPOCH + Duration::from_secs(s);
+        let files = vec![
+            (PathBuf::from("b.wav"), 40, t(2)),
+            (PathBuf::from("a.wav"), 40, t(1)),
+            (PathBuf::from("c.wav"), 40, t(3)),
+        ];
+        assert_eq!(files_to_prune(files.clone(), 120), Vec::<PathBuf>::new());
+        assert_eq!(files_to_prune(files.clone(), 100), vec![PathBuf::from("a.wav")]);
+        assert_eq!(files_to_prune(files, 40), vec![PathBuf::from("a.wav"), PathBuf::from("b.wav")]);
+    }
+
+    #[test]
+    fn test_prune_only_recordings() {
+        assert!(is_recording(Path::new("rec/20180827-222310-usb-1.2-H390_1.wav")));
+        assert!(!is_recording(Path::new("rec/ms-lx-3000_0.wav")));
+        assert!(!is_recording(Path::new("rec/20180827-222310.wav")));
+        assert!(!is_recording(Path::new("rec/20180827-222310-a-b.trace")));
+
+        let dir = env::temp_dir().join(format!("egloorator-recorder-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("20180827-222310-a-b.wav");
+        let live = dir.join("20180827-222311-c-d.wav");
+        let foreign = dir.join("ms-lx-3000_0.wav");
+        for path in &[&old, &live, &foreign] {
+            fs::write(path, vec![0u8; 100]).unwrap();
+        }
+        let mut writing = HashSet::new();
+        writing.insert(live.clone());
+        prune(&dir, 0, &writing);
+        assert!(!old.exists());
+        assert!(live.exists());
+        assert!(foreign.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{stdout, stderr, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+
+use argparse::{ArgumentParser, Store, StoreOption, List};
+use chrono::{FixedOffset, TimeZone};
+use serde_json;
+
+use backend::{AudioBackend, Cue, LevelSource, Route};
+use calibration::Calibration;
+use config::{Config, VadConfig};
+use eventlog::{record, Record};
+use events::{Events, Stamped};
+use headset::Headset;
+use hub::{Hub, SilenceChange};
+use report::{analyze, markdown, Time};
+use silence::Silence;
+use trace::{self, Trace};
+
+
+// The level traces of a night (see trace.rs) fed back through Silence and the hub
+// with other settings: other thresholds or silent and average periods. Nothing is
+// played, the conversations that would have happened are reported like those of the
+// event log of a real night, in the local time of the venue.
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub s2a: Option<f64>, // instead of the recorded thresholds
+    pub a2s: Option<f64>,
+    pub shift: f64,       // dB added to both thresholds
+    pub vad: VadConfig,
+}
+
+
+impl Settings {
+    fn thresholds(&self, recorded: &Calibration) -> Calibration {
+        Calibration {
+            s2a: self.s2a.unwrap_or(recorded.s2a) + self.shift,
+            a2s: self.a2s.unwrap_or(recorded.a2s) + self.shift,
+        }
+    }
+}
+
+
+// conversations on paper only
+struct Offline;
+struct NoRoute;
+
+
+impl Route for NoRoute {
+    fn stop(self: Box<Self>) {}
+}
+
+
+impl AudioBackend for Offline {
+    fn level_source(&self, headset: &Headset, _level_interval: f64) -> Result<Box<LevelSource>, String> {
+        Err(format!("{}: no audio in a replay", headset.id))
+    }
+
+    fn route(&self, _speaker: &Headset, _listener: &Headset, _failed: Box<Fn(String) + Send>) -> Result<Box<Route>, String> {
+        Ok(Box::new(NoRoute))
+    }
+
+    fn cue(&self, _headset: &Headset, _amplitude: f64) -> Option<Box<Cue>> {
+        None
+    }
+}
+
+
+// what the level watcher of one trace would be up to
+struct Watch {
+    next: usize, // reading
+    recorded: Calibration,
+    silence: Silence,
+    silent: bool,
+}
+
+
+// seconds since the epoch, to the millisecond like the event log
+fn time(seconds: f64, offset: FixedOffset) -> Time {
+    let millis = (seconds * 1000f64).round() as i64;
+    offset.timestamp_opt(millis / 1000, (millis % 1000) as u32 * 1000000).unwrap()
+}
+
+
+fn headset(trace: &Trace, thresholds: Calibration) -> Headset {
+    Headset {
+        id: trace.id.clone(),
+        source: format!("replay"),
+        sink: format!("replay"),
+        calibration: thresholds,
+        amplification: 0f64,
+        consent: false,
+    }
+}
+
+
+// Every reading of every trace in time order, voices are the traces' indices
+pub fn replay(traces: &[Trace], settings: &Settings) -> Vec<(Time, Record)> {
+    let (tx, _rx) = channel(); // offline routes never fail
+    let events = Events::new();
+    let subscription = events.subscribe();
+    let mut hub = Hub::new(tx, Arc::new(Offline));
+    hub.set_events(events);
+
+    let vad = &settings.vad;
+    let mut watches = Vec::new();
+    for (voice, trace) in traces.iter().enumerate() {
+        let recorded = trace.readings.first().map(|r| r.thresholds).unwrap_or(Calibration { s2a: 0f64, a2s: 0f64 });
+        let thresholds = settings.thresholds(&recorded);
+        hub.add_headset(voice, headset(trace, thresholds));
+        watches.push(Watch {
+            next: 0,
+            recorded: recorded,
+            silence: Silence::new(thresholds.s2a, thresholds.a2s, vad.silent_period, vad.average_period),
+            silent: true,
+        });
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let next = (0..traces.len()).filter(|&v| watches[v].next < traces[v].readings.len()).min_by(|&a, &b| {
+            let (ta, tb) = (traces[a].readings[watches[a].next].time, traces[b].readings[watches[b].next].time);
+            ta.partial_cmp(&tb).unwrap_or(Ordering::Equal)
+        });
+        let voice = match next {
+            Some(voice) => voice,
+            None => break,
+        };
+        let reading = &traces[voice].readings[watches[voice].next];
+        let watch = &mut watches[voice];
+        watch.next += 1;
+        // the operator changed the thresholds that night, a shift still applies
+        if reading.thresholds != watch.recorded {
+            watch.recorded = reading.thresholds;
+            let thresholds = settings.thresholds(&watch.recorded);
+            watch.silence = watch.silence.reconfigure(thresholds.s2a, thresholds.a2s, vad.silent_period, vad.average_period);
+            hub.update_headset(voice, headset(&traces[voice], thresholds));
+        }
+        watch.silence = watch.silence.input(reading.rms);
+        let silent = watch.silence.output();
+        if silent != watch.silent {
+            watch.silent = silent;
+            hub.input(&SilenceChange { who: voice, silent: silent }, reading.rms);
+            let at = time(reading.time, reading.offset);
+            while let Ok(stamped) = subscription.try_recv() {
+                match record(&Stamped { time: at.to_rfc3339(), event: stamped.event }) {
+                    Some(record) => records.push((at, record)),
+                    None => {}
+                }
+            }
+        }
+    }
+    records
+}
+
+
+pub fn main(args: Vec<String>)
+{
+    let mut files: Vec<String> = vec![];
+    let mut config_file: String = format!("");
+    let mut s2a: Option<f64> = None;
+    let mut a2s: Option<f64> = None;
+    let mut shift: f64 = 0.0;
+    let mut silent_period: Option<i64> = None;
+    let mut average_period: Option<i64> = None;
+    let mut event_log: String = format!("");
+    let mut output: String = format!("");
+    let mut top: usize = 10;
+
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Replay level traces with other settings and report what would have happened");
+        ap.refer(&mut config_file).add_option(&["-C", "--config"], Store, "Take vad settings from this config file");
+        ap.refer(&mut s2a).add_option(&["-s", "--s2a"], StoreOption, "Silent to active threshold of every headset, instead of the recorded ones");
+        ap.refer(&mut a2s).add_option(&["-a", "--a2s"], StoreOption, "Active to silent threshold of every headset, instead of the recorded ones");
+        ap.refer(&mut shift).add_option(&["--shift"], Store, "dB added to every threshold, 3 for 3 dB higher");
+        ap.refer(&mut silent_period).add_option(&["--silent-period"], StoreOption, "In level intervals, instead of vad.silent_period");
+        ap.refer(&mut average_period).add_option(&["--average-period"], StoreOption, "In level intervals, instead of vad.average_period");
+        ap.refer(&mut event_log).add_option(&["--event-log"], Store, "Also write what would have happened as an event log");
+        ap.refer(&mut output).add_option(&["-o", "--output"], Store, "Write the markdown report here instead of stdout");
+        ap.refer(&mut top).add_option(&["--top"], Store, "Number of pairs and hours to list");
+        ap.refer(&mut files).add_argument("files", List, "Level traces, one per headset").required();
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {},
+            Err(x) => process::exit(x),
+        }
+    }
+
+    let mut config = match config_file.len() {
+        0 => Config::default(),
+        _ => Config::load(Path::new(&config_file)).unwrap_or_else(|e| {
+            println!("cannot load config: {}", e);
+            process::exit(1);
+        }),
+    };
+    config.vad.silent_period = silent_period.unwrap_or(config.vad.silent_period);
+    config.vad.average_period = average_period.unwrap_or(config.vad.average_period);
+    if config.vad.average_period < 1 {
+        println!("the average period must be at least 1");
+        process::exit(1);
+    }
+
+    let mut traces = Vec::new();
+    for file in &files {
+        traces.push(trace::read(Path::new(file)).unwrap_or_else(|e| {
+            println!("cannot read trace: {}", e);
+            process::exit(1);
+        }));
+    }
+    traces.sort_by(|a, b| a.id.cmp(&b.id));
+    for pair in traces.windows(2) {
+        if pair[0].id == pair[1].id {
+            println!("more than one trace of {}", pair[0].id);
+            process::exit(1);
+        }
+    }
+
+    let settings = Settings {
+        s2a: s2a,
+        a2s: a2s,
+        shift: shift,
+        vad: config.vad.clone(),
+    };
+    let records = replay(&traces, &settings);
+
+    if event_log.len() > 0 {
+        let written = File::create(&event_log).and_then(|mut f| {
+            for &(_, ref record) in &records {
+                writeln!(f, "{}", serde_json::to_string(record).unwrap())?;
+            }
+            Ok(())
+        });
+        written.unwrap_or_else(|e| {
+            println!("cannot write {}: {}", event_log, e);
+            process::exit(1);
+        });
+    }
+
+    let text = markdown(&analyze(&records), top);
+    if output.len() > 0 {
+        fs::write(&output, text).unwrap_or_else(|e| {
+            println!("cannot write {}: {}", output, e);
+            process::exit(1);
+        });
+    } else {
+        print!("{}", text);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+    use calibration::Calibration;
+    use config::VadConfig;
+    use trace::{Reading, Trace};
+    use super::{replay, Settings};
+
+    // one reading per level interval from start, at rms while talking and -90 otherwise,
+    // recorded at utc+3
+    fn trace(id: &str, talking: &[(usize, usize)], rms: f64) -> Trace {
+        let thresholds = Calibration { s2a: -45f64, a2s: -47f64 };
+        Trace {
+            id: String::from(id),
+            readings: (0..100).map(|i| Reading {
+                time: 1535397790f64 + i as f64 * 0.1,
+                rms: if talking.iter().any(|&(from, to)| from <= i && i < to) { rms } else { -90f64 },
+                thresholds: thresholds,
+                offset: FixedOffset::east_opt(3 * 3600).unwrap(),
+            }).collect(),
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings { s2a: None, a2s: None, shift: 0f64, vad: VadConfig { level_interval: 0.1, silent_period: 5, average_period: 1 } }
+    }
+
+    fn timeline(traces: &[Trace], settings: &Settings) -> Vec<String> {
+        replay(traces, settings).iter().filter(|&&(_, ref r)| r.event == "connect" || r.event == "disconnect")
+            .map(|&(ref t, ref r)| format!("{} {} {} {}", t.format("%S%.3f"), r.event, r.headset, r.partner_headset.clone().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_replay() {
+        // b is quieter and only heard with lower thresholds
+        let traces = vec![trace("a", &[(0, 50)], -30f64), trace("b", &[(10, 30)], -48f64), trace("c", &[(60, 80)], -30f64)];
+        assert_eq!(timeline(&traces, &settings()), Vec::<String>::new());
+
+        let mut lower = settings();
+        lower.shift = -3f64;
+        assert_eq!(timeline(&traces, &lower), vec![format!("11.000 connect b a"), format!("13.400 disconnect b a")]);
+        // in the time of the venue, wherever the replay runs
+        assert_eq!(replay(&traces, &lower)[0].0.to_rfc3339(), "2018-08-27T22:23:10+03:00");
+
+        let mut absolute = lower.clone();
+        absolute.s2a = Some(-55f64);
+        absolute.a2s = Some(-57f64);
+        assert_eq!(timeline(&traces, &absolute), vec![format!("11.000 connect b a"), format!("13.400 disconnect b a")]);
+
+        let mut longer = lower.clone();
+        longer.vad.silent_period = 30;
+        assert_eq!(timeline(&traces, &longer), vec![format!("11.000 connect b a"), format!("15.900 disconnect b a"),
+                                                     format!("16.000 connect c a"), format!("17.900 disconnect a c")]);
+    }
+}
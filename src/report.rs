@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, stdout, stderr};
+use std::path::Path;
+use std::process;
+
+use argparse::{ArgumentParser, Store, List};
+use chrono::{DateTime, FixedOffset, Timelike};
+use serde_json;
+
+use eventlog::Record;
+use sessions::{pair, Sessions};
+
+
+pub type Time = DateTime<FixedOffset>;
+type Pair = (String, String); // headset ids, lower first
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversation {
+    pub pair: Pair,
+    pub start: Time,
+    pub seconds: f64,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeadsetStats {
+    pub conversations: usize,
+    pub talk: f64, // seconds in conversations
+    pub waits: Vec<f64>, // seconds from becoming active to being paired, one per wait
+}
+
+
+// What happened over the night, by headset id rather than voice since voices can
+// change between runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub first: Option<Time>,
+    pub last: Option<Time>,
+    pub conversations: Vec<Conversation>,
+    pub headsets: BTreeMap<String, HeadsetStats>,
+}
+
+
+// upper bounds in seconds of the duration distribution, the last bucket is open
+const BUCKETS: &[(f64, &str)] = &[(60f64, "under 1m"), (300f64, "1m - 5m"), (900f64, "5m - 15m"), (1800f64, "15m - 30m")];
+
+
+fn seconds(from: &Time, to: &Time) -> f64 {
+    to.signed_duration_since(*from).num_milliseconds() as f64 / 1000f64
+}
+
+
+// Every record of the given event logs, oldest first. Lines that don't parse (a log
+// cut short by a crash) are skipped with a warning.
+pub fn read_records(paths: &Vec<String>) -> Result<(Vec<(Time, Record)>, Vec<String>), String> {
+    let mut records = Vec::new();
+    let mut warnings = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().len() == 0 {
+                continue;
+            }
+            let parsed = serde_json::from_str::<Record>(line).map_err(|e| e.to_string())
+                .and_then(|r| DateTime::parse_from_rfc3339(&r.time).map(|t| (t, r)).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(record) => records.push(record),
+                Err(e) => warnings.push(format!("{}:{}: skipped, {}", path, i + 1, e)),
+            }
+        }
+    }
+    // rotated files can be given in any order
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok((records, warnings))
+}
+
+
+// Waits and conversations as counted by Sessions, conversations still going at the end
+// of the log end with its last record.
+pub fn analyze(records: &[(Time, Record)]) -> Report {
+    let mut report = Report::default();
+    let mut sessions: Sessions<String, Time> = Sessions::new();
+
+    for &(time, ref record) in records {
+        if report.first.is_none() {
+            report.first = Some(time);
+        }
+        report.last = Some(time);
+        report.headsets.entry(record.headset.clone()).or_insert(HeadsetStats::default());
+        match (&record.event[..], &record.partner_headset) {
+            ("active", _) => sessions.active(&record.headset, time),
+            ("silent", _) => sessions.silent(&record.headset),
+            ("connect", &Some(ref partner)) => {
+                for (id, since) in sessions.connect(&record.headset, partner, time) {
+                    report.headsets.entry(id).or_insert(HeadsetStats::default()).waits.push(seconds(&since, &time));
+                }
+            },
+            ("disconnect", &Some(ref partner)) => {
+                match sessions.disconnect(&record.headset, partner) {
+                    Some(start) => report.conversations.push(Conversation {
+                        pair: pair(&record.headset, partner),
+                        start: start,
+                        seconds: seconds(&start, &time),
+                    }),
+                    None => {}
+                }
+            },
+            _ => {},
+        }
+    }
+    match report.last {
+        Some(last) => for (key, start) in sessions.drain() {
+            report.conversations.push(Conversation { pair: key, start: start, seconds: seconds(&start, &last) });
+        },
+        None => {}
+    }
+    report.conversations.sort_by(|a, b| a.start.cmp(&b.start));
+
+    for conversation in &report.conversations {
+        for id in &[&conversation.pair.0, &conversation.pair.1] {
+            let stats = report.headsets.entry((*id).clone()).or_insert(HeadsetStats::default());
+            stats.conversations += 1;
+            stats.talk += conversation.seconds;
+        }
+    }
+    report
+}
+
+
+impl Report {
+    // how many conversations fall in each of BUCKETS and the open one above them
+    pub fn distribution(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = BUCKETS.iter().map(|b| (b.1, 0)).collect::<Vec<_>>();
+        counts.push(("30m and over", 0));
+        for conversation in &self.conversations {
+            let i = BUCKETS.iter().position(|b| conversation.seconds < b.0).unwrap_or(BUCKETS.len());
+            counts[i].1 += 1;
+        }
+        counts
+    }
+
+    // pairs by number of conversations, then by time together
+    pub fn pairs(&self) -> Vec<(Pair, usize, f64)> {
+        let mut pairs: BTreeMap<Pair, (usize, f64)> = BTreeMap::new();
+        for conversation in &self.conversations {
+            let entry = pairs.entry(conversation.pair.clone()).or_insert((0, 0f64));
+            entry.0 += 1;
+            entry.1 += conversation.seconds;
+        }
+        let mut pairs = pairs.into_iter().map(|(p, (n, s))| (p, n, s)).collect::<Vec<_>>();
+        pairs.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.partial_cmp(&a.2).unwrap()));
+        pairs
+    }
+
+    // conversations started and seconds talked, by the hour of day they started in
+    pub fn hours(&self) -> Vec<(u32, usize, f64)> {
+        let mut hours = (0..24).map(|h| (h, 0, 0f64)).collect::<Vec<_>>();
+        for conversation in &self.conversations {
+            let hour = &mut hours[conversation.start.hour() as usize];
+            hour.1 += 1;
+            hour.2 += conversation.seconds;
+        }
+        hours
+    }
+}
+
+
+fn duration(seconds: f64) -> String {
+    let s = seconds.round() as u64;
+    if s >= 3600 {
+        format!("{}h {:02}m", s / 3600, s % 3600 / 60)
+    } else {
+        format!("{}m {:02}s", s / 60, s % 60)
+    }
+}
+
+
+fn average(values: &[f64]) -> f64 {
+    if values.len() == 0 { 0f64 } else { values.iter().sum::<f64>() / values.len() as f64 }
+}
+
+
+pub fn markdown(report: &Report, top: usize) -> String {
+    let mut out = format!("# Egloorator session report\n\n");
+    match (report.first, report.last) {
+        (Some(first), Some(last)) => out += &format!("From {} to {} ({}).\n\n", first.to_rfc3339(), last.to_rfc3339(), duration(seconds(&first, &last))),
+        _ => out += "The event log is empty.\n\n",
+    }
+
+    let mut durations = report.conversations.iter().map(|c| c.seconds).collect::<Vec<f64>>();
+    durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    out += "## Conversations\n\n";
+    out += &format!("{} conversations", durations.len());
+    if durations.len() > 0 {
+        out += &format!(", shortest {}, median {}, average {}, longest {}",
+                        duration(durations[0]), duration(durations[durations.len() / 2]),
+                        duration(average(&durations)), duration(durations[durations.len() - 1]));
+    }
+    out += ".\n\n| Duration | Conversations |\n|---|---|\n";
+    for (bucket, count) in report.distribution() {
+        out += &format!("| {} | {} |\n", bucket, count);
+    }
+
+    out += "\n## Headsets\n\n| Headset | Conversations | Talk time | Waits | Average wait | Longest wait |\n|---|---|---|---|---|---|\n";
+    for (id, stats) in &report.headsets {
+        let longest = stats.waits.iter().cloned().fold(0f64, f64::max);
+        out += &format!("| {} | {} | {} | {} | {} | {} |\n", id, stats.conversations, duration(stats.talk),
+                        stats.waits.len(), duration(average(&stats.waits)), duration(longest));
+    }
+
+    out += "\n## Most frequent pairs\n\n| Pair | Conversations | Together |\n|---|---|---|\n";
+    for (pair, count, seconds) in report.pairs().into_iter().take(top) {
+        out += &format!("| {} - {} | {} | {} |\n", pair.0, pair.1, count, duration(seconds));
+    }
+
+    let mut hours = report.hours().into_iter().filter(|h| h.1 > 0).collect::<Vec<_>>();
+    hours.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    out += "\n## Busiest hours\n\n| Hour | Conversations started | Talk time |\n|---|---|---|\n";
+    for (hour, count, seconds) in hours.into_iter().take(top) {
+        out += &format!("| {:02}:00 | {} | {} |\n", hour, count, duration(seconds));
+    }
+    out
+}
+
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+
+// one csv per table, file name and contents
+pub fn csv(report: &Report) -> Vec<(&'static str, String)> {
+    let mut conversations = format!("start,headset_one,headset_two,seconds\n");
+    for c in &report.conversations {
+        conversations += &format!("{},{},{},{:.1}\n", c.start.to_rfc3339(), csv_field(&c.pair.0), csv_field(&c.pair.1), c.seconds);
+    }
+    let mut headsets = format!("headset,conversations,talk_seconds,waits,wait_seconds\n");
+    for (id, stats) in &report.headsets {
+        headsets += &format!("{},{},{:.1},{},{:.1}\n", csv_field(id), stats.conversations, stats.talk,
+                             stats.waits.len(), stats.waits.iter().sum::<f64>());
+    }
+    let mut pairs = format!("headset_one,headset_two,conversations,seconds\n");
+    for (pair, count, seconds) in report.pairs() {
+        pairs += &format!("{},{},{},{:.1}\n", csv_field(&pair.0), csv_field(&pair.1), count, seconds);
+    }
+    let mut hours = format!("hour,conversations,talk_seconds\n");
+    for (hour, count, seconds) in report.hours() {
+        hours += &format!("{},{},{:.1}\n", hour, count, seconds);
+    }
+    vec![("conversations.csv", conversations), ("headsets.csv", headsets), ("pairs.csv", pairs), ("hours.csv", hours)]
+}
+
+
+fn write_csv(dir: &Path, report: &Report) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, contents) in csv(report) {
+        fs::write(dir.join(name), contents)?;
+    }
+    Ok(())
+}
+
+
+// Statistics of a night from its event logs (see eventlog.rs), as markdown on stdout
+// or into a file, and optionally as csv files for a spreadsheet.
+pub fn main(args: Vec<String>)
+{
+    let mut files: Vec<String> = vec![];
+    let mut output: String = format!("");
+    let mut csv_dir: String = format!("");
+    let mut top: usize = 10;
+
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Report on a session from its event logs");
+        ap.refer(&mut output).add_option(&["-o", "--output"], Store, "Write the markdown report here instead of stdout");
+        ap.refer(&mut csv_dir).add_option(&["--csv"], Store, "Also write the tables as csv files into this directory");
+        ap.refer(&mut top).add_option(&["--top"], Store, "Number of pairs and hours to list");
+        ap.refer(&mut files).add_argument("files", List, "Event logs, rotated ones included, in any order").required();
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {},
+            Err(x) => process::exit(x),
+        }
+    }
+
+    let (records, warnings) = read_records(&files).unwrap_or_else(|e| {
+        println!("cannot read event log: {}", e);
+        process::exit(1);
+    });
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let report = analyze(&records);
+
+    let text = markdown(&report, top);
+    if output.len() > 0 {
+        fs::write(&output, text).unwrap_or_else(|e| {
+            println!("cannot write {}: {}", output, e);
+            process::exit(1);
+        });
+    } else {
+        print!("{}", text);
+    }
+    if csv_dir.len() > 0 {
+        write_csv(Path::new(&csv_dir), &report).unwrap_or_else(|e| {
+            println!("cannot write csv into {}: {}", csv_dir, e);
+            process::exit(1);
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use eventlog::Record;
+    use super::{analyze, csv, markdown, Time};
+
+    fn at(time: &str, event: &str, headset: &str, partner: Option<&str>) -> (Time, Record) {
+        let time = format!("2018-08-27T{}+02:00", time);
+        let record = Record {
+            time: time.clone(),
+            event: String::from(event),
+            voice: 0,
+            headset: String::from(headset),
+            rms: None,
+            s2a: None,
+            a2s: None,
+            partner: partner.map(|_| 1),
+            partner_headset: partner.map(String::from),
+            error: None,
+            restarts: None,
+        };
+        (DateTime::parse_from_rfc3339(&time).unwrap(), record)
+    }
+
+    fn night() -> Vec<(Time, Record)> {
+        vec![
+            at("21:00:00", "active", "a", None),
+            at("21:00:30", "active", "b", None),
+            at("21:00:30", "connect", "b", Some("a")),
+            at("21:02:30", "disconnect", "a", Some("b")),
+            at("21:10:00", "active", "c", None),
+            at("21:10:05", "silent", "c", None), // gave up
+            at("22:00:00", "active", "c", None),
+            at("22:00:20", "connect", "c", Some("a")),
+            at("22:10:00", "disconnect", "c", Some("a")),
+            at("22:30:00", "connect", "a", Some("b")),
+            at("22:31:00", "error", "c", None), // still talking when the log ends
+        ]
+    }
+
+    #[test]
+    fn test_analyze() {
+        let report = analyze(&night());
+        assert_eq!(report.conversations.iter().map(|c| c.seconds).collect::<Vec<f64>>(), vec![120f64, 580f64, 60f64]);
+        let a = &report.headsets["a"];
+        assert_eq!((a.conversations, a.talk), (3, 760f64));
+        assert_eq!(a.waits, vec![30f64]);
+        assert_eq!(report.headsets["b"].waits, vec![0f64]);
+        assert_eq!(report.headsets["c"].waits, vec![20f64]);
+        assert_eq!(report.distribution()[..3], [("under 1m", 0), ("1m - 5m", 2), ("5m - 15m", 1)]);
+        assert_eq!(report.pairs()[0], ((format!("a"), format!("b")), 2, 180f64));
+        let hours = report.hours();
+        assert_eq!((hours[21].1, hours[22].1), (1, 2));
+    }
+
+    #[test]
+    fn test_output() {
+        let report = analyze(&night());
+        let text = markdown(&report, 10);
+        assert!(text.contains("3 conversations, shortest 1m 00s, median 2m 00s"));
+        assert!(text.contains("| a | 3 | 12m 40s | 1 | 0m 30s | 0m 30s |"));
+        assert!(text.contains("| a - b | 2 | 3m 00s |"));
+        assert!(text.contains("| 22:00 | 2 | 10m 40s |"));
+        let files = csv(&report);
+        assert_eq!(files[0].1.lines().nth(1), Some("2018-08-27T21:00:30+02:00,a,b,120.0"));
+        assert_eq!(files[3].1.lines().count(), 25);
+        assert_eq!(markdown(&analyze(&[]), 10).contains("empty"), true);
+    }
+}
@@ -0,0 +1,231 @@
+use calibration::Calibration;
+use config::Config;
+use simulation::{headset, Simulation};
+
+
+// Conversation scripts for the simulation, one statement per line, # starts a comment:
+//
+//   voices 6                     headsets 0 to 5, default: up to the highest voice used
+//   silent_period 10             in level intervals, like vad.silent_period
+//   average_period 1             likewise
+//   thresholds -45 -47           s2a and a2s of every headset, dB
+//   run 20s                      default: until 10s after the last talk ends
+//
+//   t=0.0 voice 2 talks for 3s at -30dB
+//   t=4 voice 3 talks at -35dB   and never stops
+//   t=1.5 voice 4 coughs         a short loud burst
+//
+//   expect t=0.50 connect 2 0
+//   expect t=3.90 disconnect 2 0
+//
+// Voices are the hub's voices, their headset ids are the voice numbers. The script is
+// run through the level watchers' Silence and the hub's Egloorator, expect lines are
+// the connect and disconnect timeline that should come out of it.
+
+const COUGH: (f64, f64) = (0.2, -20f64); // seconds, dB
+const AFTER: f64 = 10f64; // seconds to run past the last talk
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Talk {
+    voice: usize,
+    start: f64,
+    end: f64, // infinite for someone who never stops
+    rms: f64,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    config: Config,
+    voices: usize,
+    calibration: Calibration,
+    talks: Vec<Talk>,
+    run: Option<f64>,
+    pub expected: Vec<String>,
+}
+
+
+// a number with an optional prefix and unit: t=1.5, 3s, -30dB
+fn number(token: Option<&str>, prefix: &str, unit: &str) -> Result<f64, String> {
+    let token = token.ok_or(format!("expected a number"))?;
+    let stripped = token.trim_start_matches(prefix).trim_end_matches(unit);
+    if !token.starts_with(prefix) || !token.ends_with(unit) || stripped.len() == 0 {
+        return Err(format!("expected {}<number>{}, got `{}`", prefix, unit, token));
+    }
+    stripped.parse::<f64>().map_err(|_| format!("`{}` is not a number", token))
+}
+
+
+fn voice(token: Option<&str>) -> Result<usize, String> {
+    let token = token.ok_or(format!("expected a voice"))?;
+    token.parse::<usize>().map_err(|_| format!("`{}` is not a voice", token))
+}
+
+
+fn keyword(token: Option<&str>, expected: &str) -> Result<(), String> {
+    match token {
+        Some(t) if t == expected => Ok(()),
+        Some(t) => Err(format!("expected `{}`, got `{}`", expected, t)),
+        None => Err(format!("expected `{}`", expected)),
+    }
+}
+
+
+// t=T voice N ..., after the t=T
+fn talk(start: f64, words: &[&str]) -> Result<Talk, String> {
+    let mut words = words.iter().cloned();
+    keyword(words.next(), "voice")?;
+    let voice = voice(words.next())?;
+    let talk = match words.next() {
+        Some("coughs") => Talk { voice: voice, start: start, end: start + COUGH.0, rms: COUGH.1 },
+        Some("talks") => {
+            match words.next() {
+                Some("for") => {
+                    let seconds = number(words.next(), "", "s")?;
+                    keyword(words.next(), "at")?;
+                    Talk { voice: voice, start: start, end: start + seconds, rms: number(words.next(), "", "dB")? }
+                },
+                Some("at") => Talk { voice: voice, start: start, end: ::std::f64::INFINITY, rms: number(words.next(), "", "dB")? },
+                _ => return Err(format!("expected `talks for <seconds>s at <rms>dB` or `talks at <rms>dB`")),
+            }
+        },
+        _ => return Err(format!("expected `talks` or `coughs`")),
+    };
+    match words.next() {
+        Some(extra) => Err(format!("unexpected `{}`", extra)),
+        None => Ok(talk),
+    }
+}
+
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut scenario = Scenario {
+            config: Config::default(),
+            voices: 0,
+            calibration: Calibration { s2a: -45f64, a2s: -47f64 },
+            talks: Vec::new(),
+            run: None,
+            expected: Vec::new(),
+        };
+        let mut voices = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            let result = match words.first().cloned() {
+                None => Ok(()),
+                Some("voices") => voice(words.get(1).cloned()).map(|n| voices = Some(n)),
+                Some("silent_period") => number(words.get(1).cloned(), "", "").map(|n| scenario.config.vad.silent_period = n as i64),
+                Some("average_period") => number(words.get(1).cloned(), "", "").map(|n| scenario.config.vad.average_period = n as i64),
+                Some("thresholds") => number(words.get(1).cloned(), "", "").and_then(|s2a| {
+                    number(words.get(2).cloned(), "", "").map(|a2s| scenario.calibration = Calibration { s2a: s2a, a2s: a2s })
+                }),
+                Some("run") => number(words.get(1).cloned(), "", "s").map(|s| scenario.run = Some(s)),
+                Some("expect") => {
+                    match (number(words.get(1).cloned(), "t=", ""), words.get(2)) {
+                        (Ok(t), Some(&what)) if what == "connect" || what == "disconnect" => {
+                            scenario.expected.push(format!("t={:.2} {}", t, words[2..].join(" ")));
+                            Ok(())
+                        },
+                        (Err(e), _) => Err(e),
+                        _ => Err(format!("expected `expect t=<seconds> connect|disconnect <voice> <voice>`")),
+                    }
+                },
+                Some(first) if first.starts_with("t=") => {
+                    number(Some(first), "t=", "").and_then(|start| talk(start, &words[1..])).map(|talk| scenario.talks.push(talk))
+                },
+                Some(first) => Err(format!("unknown statement `{}`", first)),
+            };
+            result.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        let used = scenario.talks.iter().map(|t| t.voice + 1).max().unwrap_or(0);
+        scenario.voices = voices.unwrap_or(used);
+        if used > scenario.voices {
+            return Err(format!("voice {} used, but there are only {} voices", used - 1, scenario.voices));
+        }
+        Ok(scenario)
+    }
+
+    fn duration(&self) -> f64 {
+        match self.run {
+            Some(run) => run,
+            None => self.talks.iter().map(|t| if t.end.is_finite() { t.end } else { t.start }).fold(0f64, f64::max) + AFTER,
+        }
+    }
+
+    // the connect and disconnect timeline, formatted like the expect lines
+    pub fn run(&self) -> Vec<String> {
+        let headsets = (0..self.voices).map(|v| headset(&format!("{}", v), self.calibration)).collect();
+        let mut sim = Simulation::new(self.config.clone(), headsets);
+        for talk in &self.talks {
+            sim.backend().talk(&format!("{}", talk.voice), talk.start, talk.end, talk.rms);
+        }
+        sim.run_for(self.duration());
+        sim.actions().iter().filter_map(|action| {
+            let mut parts = action.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(t), Some(what)) if what.starts_with("connect ") || what.starts_with("disconnect ") => Some(format!("t={} {}", t, what)),
+                _ => None,
+            }
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Scenario;
+
+    // the timeline is printed in full on a mismatch, ready to paste as expect lines
+    fn check(text: &str) {
+        let scenario = Scenario::parse(text).unwrap();
+        let timeline = scenario.run();
+        if timeline != scenario.expected {
+            let lines = timeline.iter().map(|l| format!("expect {}", l)).collect::<Vec<String>>();
+            panic!("timeline differs, got\n{}", lines.join("\n"));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let scenario = Scenario::parse("silent_period 5 # half a second\n\
+                                        t=0.0 voice 2 talks for 3s at -30dB\n\
+                                        t=1.5 voice 4 coughs\n\
+                                        t=2 voice 0 talks at -35dB\n\
+                                        expect t=0.5 connect 2 0\n").unwrap();
+        assert_eq!(scenario.voices, 5);
+        assert_eq!(scenario.config.vad.silent_period, 5);
+        assert_eq!(scenario.talks.len(), 3);
+        assert_eq!((scenario.talks[1].start, scenario.talks[1].end), (1.5, 1.7));
+        assert!(scenario.talks[2].end.is_infinite());
+        assert_eq!(scenario.duration(), 13f64);
+        assert_eq!(scenario.expected, vec![format!("t=0.50 connect 2 0")]);
+
+        assert_eq!(Scenario::parse("\nt=1 voice 2 sings").unwrap_err(), "line 2: expected `talks` or `coughs`");
+        assert_eq!(Scenario::parse("t=1 voice 2 talks for 3 at -30dB").unwrap_err(), "line 1: expected <number>s, got `3`");
+        assert_eq!(Scenario::parse("voices 2\nt=1 voice 2 coughs").unwrap_err(), "voice 2 used, but there are only 2 voices");
+        assert!(Scenario::parse("expect t=1 pair 1 2").is_err());
+        assert!(Scenario::parse("shout").is_err());
+    }
+
+    #[test]
+    fn test_crowded_room() {
+        check(include_str!("../scenarios/crowded_room.scenario"));
+    }
+
+    #[test]
+    fn test_crosstalk() {
+        check(include_str!("../scenarios/crosstalk.scenario"));
+    }
+
+    #[test]
+    fn test_never_stops() {
+        check(include_str!("../scenarios/never_stops.scenario"));
+    }
+
+    #[test]
+    fn test_coughs() {
+        check(include_str!("../scenarios/coughs.scenario"));
+    }
+}
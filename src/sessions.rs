@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+
+// Who is waiting to be paired and which conversations are going on, as the silence
+// changes, connects and disconnects come in. The report follows headset ids through an
+// event log, the metrics follow voices live, both count waits and conversations here so
+// their numbers agree.
+//
+// A wait starts when a headset not in a conversation becomes active and ends when it is
+// paired; going silent first is not counted. A conversation runs from connect to
+// disconnect.
+
+pub struct Sessions<K, T> {
+    waiting: HashMap<K, T>,
+    open: HashMap<(K, K), T>,
+}
+
+
+// the lower one first, whichever side connected
+pub fn pair<K: Ord + Clone>(one: &K, two: &K) -> (K, K) {
+    if one < two { (one.clone(), two.clone()) } else { (two.clone(), one.clone()) }
+}
+
+
+impl<K: Ord + Hash + Clone, T: Copy> Sessions<K, T> {
+    pub fn new() -> Sessions<K, T> {
+        Sessions {
+            waiting: HashMap::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn talking(&self, who: &K) -> bool {
+        self.open.keys().any(|p| p.0 == *who || p.1 == *who)
+    }
+
+    pub fn active(&mut self, who: &K, time: T) {
+        if !self.talking(who) {
+            self.waiting.entry(who.clone()).or_insert(time);
+        }
+    }
+
+    pub fn silent(&mut self, who: &K) {
+        self.waiting.remove(who);
+    }
+
+    // the waits this ends, who and since when
+    pub fn connect(&mut self, one: &K, two: &K, time: T) -> Vec<(K, T)> {
+        self.open.entry(pair(one, two)).or_insert(time);
+        let mut waits = Vec::new();
+        for who in &[one, two] {
+            match self.waiting.remove(*who) {
+                Some(since) => waits.push(((*who).clone(), since)),
+                None => {}
+            }
+        }
+        waits
+    }
+
+    // when the conversation started, None for one that was never connected
+    pub fn disconnect(&mut self, one: &K, two: &K) -> Option<T> {
+        self.open.remove(&pair(one, two))
+    }
+
+    // conversations still going, with when they started
+    pub fn drain(&mut self) -> Vec<((K, K), T)> {
+        self.open.drain().collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{pair, Sessions};
+
+    #[test]
+    fn test_sessions() {
+        assert_eq!(pair(&3, &1), (1, 3));
+        let mut sessions = Sessions::new();
+        sessions.active(&1, 0);
+        sessions.active(&1, 5);
+        sessions.active(&2, 10);
+        sessions.silent(&2);
+        sessions.active(&2, 20);
+        assert_eq!(sessions.connect(&2, &1, 20), vec![(2, 20), (1, 0)]);
+        assert!(sessions.talking(&1));
+        // talking again within a conversation is no wait
+        sessions.active(&1, 30);
+        sessions.active(&3, 30);
+        assert_eq!(sessions.connect(&3, &4, 40), vec![(3, 30)]);
+        assert_eq!(sessions.disconnect(&1, &2), Some(20));
+        assert_eq!(sessions.disconnect(&1, &2), None);
+        assert_eq!(sessions.drain(), vec![((3, 4), 40)]);
+    }
+}
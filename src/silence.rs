@@ -6,7 +6,7 @@ pub struct Silence {
     avg_rms: f64, // running average computation
     pub silent_current: i64, // time there has been silence
 
-    // parameters (constant since construction)
+    // parameters (changed only by reconfigure)
     silent_period: i64,
     become_silent_threshold: f64,
     become_active_threshold: f64, // hysteresis needs these two to be different
@@ -61,6 +61,17 @@ impl Silence {
         }
     }
 
+    // new thresholds and periods, keeping the current state so nobody is cut off
+    pub fn reconfigure(&self, silent_threshold: f64, active_threshold: f64, silent_period: i64, average_period: i64) -> Silence {
+        Silence {
+            become_silent_threshold: silent_threshold,
+            become_active_threshold: active_threshold,
+            silent_period: silent_period,
+            average_period: average_period,
+            .. *self
+        }
+    }
+
     pub fn output(&self) -> bool {
         self.silent
     }
@@ -88,6 +99,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reconfigure() -> ()
+    {
+        let s = Silence::new(LIMIT_SILENCE, LIMIT_TALK, SILENCE_COUNT, AVERAGE_COUNT).input(LIMIT_TALK);
+        assert!(!s.output());
+        // still active after the change, but the new threshold applies
+        let s = s.reconfigure(LIMIT_SILENCE + 1.0, LIMIT_TALK + 1.0, 1, AVERAGE_COUNT);
+        assert!(!s.output());
+        assert!(s.input(LIMIT_SILENCE + 0.5).output());
+    }
+
     fn test_silence_helper(inp: Vec<f64>, outp: Vec<bool>) -> () {
         let mut s = Silence::new(LIMIT_SILENCE, LIMIT_TALK, SILENCE_COUNT, AVERAGE_COUNT);
         let mut i = 0;
@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use backend::{AudioBackend, Cue, LevelEvent, LevelSource, Route, SharedBackend};
+use calibration::Calibration;
+use config::Config;
+use events::{Event, Events, Stamped};
+use headset::Headset;
+use hotplug::DeviceWatcher;
+use hub::{Hub, Message};
+use metrics::Metrics;
+use status::{SharedStatus, Status};
+use watchers::Watchers;
+
+
+// egloorator without audio, for tests. Every headset plays a scripted rms trace, one
+// level reading per tick of a virtual clock. The level watchers, the hub and what the
+// coordinator does with its messages run as usual, and everything that happens ends
+// up in an action log, cues (cue a on, cue a off) included:
+//
+//   0.00 active a
+//   0.50 active b
+//   0.50 connect b a
+//   0.50 route b -> a
+//   0.50 route a -> b
+//   2.30 silent a
+//   2.30 disconnect a b
+//   2.30 unroute a -> b
+//   2.30 unroute b -> a
+//
+// Each tick every level source gets its reading in turn, ordered by headset id, and
+// has handled it before the next one gets its own. The messages it produced are then
+// handed to the hub in order. Sources never fail, so restarts after level errors,
+// which back off in real time, are not simulated.
+
+pub const FLOOR: f64 = -90f64; // dB, the level of a headset nobody talks into
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    start: f64,
+    end: f64,
+    rms: f64,
+}
+
+
+// whose turn it is to take a reading
+#[derive(Debug, Default)]
+struct Turn {
+    pending: Option<u64>, // tick to read
+    waiting: bool,        // back in next(), done with the previous reading
+}
+
+
+#[derive(Default)]
+struct State {
+    tick: u64,
+    sources: BTreeMap<String, Turn>,
+    traces: HashMap<String, Vec<Segment>>,
+    actions: Vec<String>,
+}
+
+
+impl State {
+    // the latest segment covering t wins, silence where none does
+    fn rms(&self, id: &String, t: f64) -> f64 {
+        let t = t + 1e-9; // ticks are sums of floats
+        match self.traces.get(id) {
+            Some(segments) => segments.iter().rev().find(|s| s.start <= t && t < s.end).map(|s| s.rms).unwrap_or(FLOOR),
+            None => FLOOR,
+        }
+    }
+}
+
+
+type SharedState = Arc<(Mutex<State>, Condvar)>;
+
+
+pub struct SimBackend {
+    step: f64, // seconds per tick
+    state: SharedState,
+}
+
+
+struct SimLevelSource {
+    id: String,
+    step: f64,
+    state: SharedState,
+}
+
+
+impl LevelSource for SimLevelSource {
+    fn next(&mut self, timeout: Duration) -> Option<LevelEvent> {
+        let deadline = Instant::now() + timeout;
+        let &(ref lock, ref cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            let tick = match state.sources.get_mut(&self.id) {
+                Some(turn) => {
+                    let tick = turn.pending.take();
+                    turn.waiting = tick.is_none();
+                    tick
+                },
+                None => None,
+            };
+            match tick {
+                Some(tick) => return Some(LevelEvent::Level(state.rms(&self.id, tick as f64 * self.step))),
+                None => {}
+            }
+            cvar.notify_all();
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+
+impl Drop for SimLevelSource {
+    fn drop(&mut self) {
+        let &(ref lock, ref cvar) = &*self.state;
+        lock.lock().unwrap().sources.remove(&self.id);
+        cvar.notify_all();
+    }
+}
+
+
+struct SimRoute {
+    what: String,
+    state: SharedState,
+}
+
+
+impl Route for SimRoute {
+    fn stop(self: Box<Self>) {
+        self.state.0.lock().unwrap().actions.push(format!("unroute {}", self.what));
+    }
+}
+
+
+struct SimCue {
+    id: String,
+    playing: bool,
+    state: SharedState,
+}
+
+
+impl SimCue {
+    fn set(&mut self, playing: bool) {
+        if self.playing != playing {
+            self.playing = playing;
+            self.state.0.lock().unwrap().actions.push(format!("cue {} {}", self.id, if playing { "on" } else { "off" }));
+        }
+    }
+}
+
+
+impl Cue for SimCue {
+    fn play(&mut self) {
+        self.set(true);
+    }
+
+    fn pause(&mut self) {
+        self.set(false);
+    }
+}
+
+
+impl SimBackend {
+    pub fn new(step: f64) -> SimBackend {
+        SimBackend {
+            step: step,
+            state: Arc::new((Mutex::new(State::default()), Condvar::new())),
+        }
+    }
+
+    // id is at rms from start until end, in seconds of virtual time
+    pub fn talk(&self, id: &str, start: f64, end: f64, rms: f64) {
+        let mut state = self.state.0.lock().unwrap();
+        state.traces.entry(String::from(id)).or_insert(Vec::new()).push(Segment { start: start, end: end, rms: rms });
+    }
+
+    pub fn time(&self) -> f64 {
+        self.state.0.lock().unwrap().tick as f64 * self.step
+    }
+
+    // routes and cues since the last call
+    fn take_actions(&self) -> Vec<String> {
+        self.state.0.lock().unwrap().actions.drain(..).collect()
+    }
+
+    // until n sources are waiting for their first reading
+    fn wait_ready(&self, n: usize) {
+        let &(ref lock, ref cvar) = &*self.state;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut state = lock.lock().unwrap();
+        while state.sources.len() != n || state.sources.values().any(|turn| !turn.waiting) {
+            if Instant::now() >= deadline {
+                panic!("simulation: {} of {} level sources started", state.sources.len(), n);
+            }
+            state = cvar.wait_timeout(state, Duration::from_millis(100)).unwrap().0;
+        }
+    }
+
+    // one reading for every source, one after the other, returns the time read
+    fn advance(&self) -> f64 {
+        let &(ref lock, ref cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let tick = state.tick;
+        let ids = state.sources.keys().cloned().collect::<Vec<String>>();
+        for id in ids {
+            match state.sources.get_mut(&id) {
+                Some(turn) => {
+                    turn.pending = Some(tick);
+                    turn.waiting = false;
+                },
+                None => continue,
+            }
+            cvar.notify_all();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let done = match state.sources.get(&id) {
+                    Some(turn) => turn.pending.is_none() && turn.waiting,
+                    None => true, // stopped
+                };
+                if done {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    panic!("simulation: {} did not take its reading at tick {}", id, tick);
+                }
+                state = cvar.wait_timeout(state, Duration::from_millis(100)).unwrap().0;
+            }
+        }
+        state.tick += 1;
+        tick as f64 * self.step
+    }
+}
+
+
+impl AudioBackend for SimBackend {
+    fn level_source(&self, headset: &Headset, _level_interval: f64) -> Result<Box<LevelSource>, String> {
+        let &(ref lock, ref cvar) = &*self.state;
+        lock.lock().unwrap().sources.insert(headset.id.clone(), Turn::default());
+        cvar.notify_all();
+        Ok(Box::new(SimLevelSource { id: headset.id.clone(), step: self.step, state: self.state.clone() }))
+    }
+
+    fn route(&self, speaker: &Headset, listener: &Headset, _failed: Box<Fn(String) + Send>) -> Result<Box<Route>, String> {
+        let what = format!("{} -> {}", speaker.id, listener.id);
+        self.state.0.lock().unwrap().actions.push(format!("route {}", what));
+        Ok(Box::new(SimRoute { what: what, state: self.state.clone() }))
+    }
+
+    fn cue(&self, headset: &Headset, _amplitude: f64) -> Option<Box<Cue>> {
+        Some(Box::new(SimCue { id: headset.id.clone(), playing: false, state: self.state.clone() }))
+    }
+}
+
+
+// a headset that only exists in the simulation
+pub fn headset(id: &str, calibration: Calibration) -> Headset {
+    Headset {
+        id: String::from(id),
+        source: format!("sim-{}", id),
+        sink: format!("sim-{}", id),
+        calibration: calibration,
+        amplification: 0f64,
+        consent: false,
+    }
+}
+
+
+// The hub and the level watchers of main's run(), on a SimBackend, with this thread
+// as the coordinator
+pub struct Simulation {
+    backend: Arc<SimBackend>,
+    config: Config,
+    hub: Hub,
+    rx: Receiver<Message>,
+    events: Receiver<Stamped>,
+    status: SharedStatus,
+    watchers: Watchers,
+    device_watcher: DeviceWatcher,
+    actions: Vec<String>,
+    quit: bool,
+}
+
+
+impl Simulation {
+    pub fn new(config: Config, headsets: Vec<Headset>) -> Simulation {
+        let backend = Arc::new(SimBackend::new(config.vad.level_interval));
+        let shared: SharedBackend = backend.clone();
+        let (tx, rx) = channel();
+        let events = Events::new();
+        let subscription = events.subscribe();
+        let metrics = Metrics::new();
+        let status: SharedStatus = Arc::new(Mutex::new(Status::default()));
+        let mut hub = Hub::new(tx.clone(), shared.clone());
+        hub.set_events(events);
+        hub.set_metrics(metrics.clone());
+        let mut simulation = Simulation {
+            backend: backend,
+            config: config,
+            hub: hub,
+            rx: rx,
+            events: subscription,
+            status: status.clone(),
+            watchers: Watchers::new(shared, tx, status, metrics),
+            device_watcher: DeviceWatcher::new(),
+            actions: Vec::new(),
+            quit: false,
+        };
+        simulation.plug(headsets);
+        simulation
+    }
+
+    pub fn backend(&self) -> &SimBackend {
+        &self.backend
+    }
+
+    // the headsets present from now on, like a hotplug poll finding them
+    pub fn plug(&mut self, headsets: Vec<Headset>) {
+        let changes = self.device_watcher.update(headsets);
+        self.watchers.apply(changes, &self.config);
+        self.backend.wait_ready(self.device_watcher.present().len());
+        let t = self.backend.time();
+        self.dispatch(t);
+    }
+
+    pub fn run_for(&mut self, seconds: f64) {
+        let ticks = (seconds / self.config.vad.level_interval).round() as u64;
+        for _ in 0..ticks {
+            if self.quit {
+                break;
+            }
+            let t = self.backend.advance();
+            self.dispatch(t);
+        }
+    }
+
+    // what the coordinator loop does with everything that came in
+    fn dispatch(&mut self, t: f64) {
+        self.record(t);
+        while let Ok(msg) = self.rx.try_recv() {
+            if !self.hub.handle(msg) {
+                self.quit = true;
+            }
+            self.hub.publish(&mut self.status.lock().unwrap());
+            self.record(t);
+        }
+    }
+
+    fn record(&mut self, t: f64) {
+        while let Ok(stamped) = self.events.try_recv() {
+            let what = match stamped.event {
+                Event::SilenceChange { ref id, silent, .. } => format!("{} {}", if silent { "silent" } else { "active" }, id),
+                Event::Connect { ref ids, .. } => format!("connect {} {}", ids.0, ids.1),
+                Event::Disconnect { ref ids, .. } => format!("disconnect {} {}", ids.0, ids.1),
+                Event::Error { ref id, ref error, .. } => format!("error {}: {}", id, error),
+                Event::Restart { ref id, .. } => format!("restart {}", id),
+                Event::Levels { .. } => continue,
+            };
+            self.actions.push(format!("{:.2} {}", t, what));
+        }
+        for what in self.backend.take_actions() {
+            self.actions.push(format!("{:.2} {}", t, what));
+        }
+    }
+
+    pub fn actions(&self) -> &Vec<String> {
+        &self.actions
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn send(&mut self, msg: Message) {
+        if !self.hub.handle(msg) {
+            self.quit = true;
+        }
+        self.hub.publish(&mut self.status.lock().unwrap());
+        let t = self.backend.time();
+        self.record(t);
+    }
+}
+
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.watchers.stop_all();
+        self.hub.shutdown();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calibration::Calibration;
+    use config::Config;
+    use headset::Headset;
+    use hub::Message;
+    use super::{headset, Simulation};
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.vad.silent_period = 4;
+        config
+    }
+
+    fn headsets(ids: &[&str]) -> Vec<Headset> {
+        ids.iter().map(|id| headset(id, Calibration { s2a: -45f64, a2s: -47f64 })).collect()
+    }
+
+    #[test]
+    fn test_conversation() {
+        let mut sim = Simulation::new(config(), headsets(&["a", "b", "c"]));
+        sim.backend().talk("a", 0f64, 2f64, -20f64);
+        sim.backend().talk("b", 0.5, 3f64, -30f64);
+        sim.backend().talk("c", 1f64, 1.5, -30f64);
+        sim.run_for(4f64);
+        assert_eq!(sim.actions(), &vec![
+            format!("0.00 cue a on"),
+            format!("0.00 active a"),
+            format!("0.50 cue b on"),
+            format!("0.50 active b"),
+            format!("0.50 connect b a"),
+            format!("0.50 route b -> a"),
+            format!("0.50 route a -> b"),
+            format!("0.60 cue a off"),
+            format!("1.00 cue c on"),
+            format!("1.00 active c"),
+            format!("1.10 cue b off"),
+            format!("1.60 cue c off"),
+            format!("1.80 silent c"),
+            format!("2.30 silent a"),
+            format!("2.30 disconnect a b"),
+            format!("2.30 unroute a -> b"),
+            format!("2.30 unroute b -> a"),
+            format!("3.30 silent b"),
+        ]);
+        let status = sim.status();
+        assert_eq!(status.pairs, vec![]);
+        assert_eq!(status.single, None);
+    }
+
+    #[test]
+    fn test_force_connect() {
+        let mut sim = Simulation::new(config(), headsets(&["a", "b"]));
+        sim.backend().talk("a", 0f64, 1f64, -20f64);
+        sim.run_for(0.2);
+        sim.send(Message::ForceConnect(0, 1));
+        assert_eq!(sim.status().routes, vec![(0, 1), (1, 0)]);
+        assert_eq!(sim.status().single, None);
+        sim.run_for(1.8);
+        assert_eq!(sim.actions(), &vec![
+            format!("0.00 cue a on"),
+            format!("0.00 active a"),
+            format!("0.20 connect a b"),
+            format!("0.20 route a -> b"),
+            format!("0.20 route b -> a"),
+            format!("0.60 cue a off"),
+            format!("1.30 silent a"),
+            format!("1.30 disconnect a b"),
+            format!("1.30 unroute a -> b"),
+            format!("1.30 unroute b -> a"),
+        ]);
+    }
+}
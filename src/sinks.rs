@@ -0,0 +1,149 @@
+use devices::Device;
+
+
+// Which sink each source's wearer hears, i.e. the other half of the headset
+#[derive(Debug, PartialEq)]
+pub struct SinkMatch {
+    pub sinks: Vec<Option<String>>, // per source, None when no sink was found
+    pub unmatched_sinks: Vec<String>,
+    pub unknown_sinks: Vec<String>, // override sinks the server does not have
+}
+
+
+// parse "source=sink" override pairs
+pub fn parse_overrides(overrides: &Vec<String>) -> Result<Vec<(String, String)>, String>
+{
+    overrides.iter().map(|o| {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(source), Some(sink)) if source.len() > 0 && sink.len() > 0 => Ok((String::from(source), String::from(sink))),
+            _ => Err(format!("bad sink override `{}`, expected source=sink", o)),
+        }
+    }).collect()
+}
+
+
+// Overrides win, otherwise a source is matched to an unused sink on the same sound card,
+// or of the same bluetooth device. Override sinks are set aside first so no earlier
+// source is auto-matched to a sink a later one was told to use.
+pub fn match_sinks(sources: &Vec<Device>, sinks: &Vec<Device>, overrides: &Vec<(String, String)>) -> SinkMatch
+{
+    let mut used = vec![false; sinks.len()];
+    let mut unknown_sinks = Vec::new();
+    let overridden: Vec<Option<String>> = sources.iter()
+        .map(|source| overrides.iter().find(|o| o.0 == source.name).map(|o| o.1.clone()))
+        .collect();
+
+    for sink in overridden.iter().filter_map(|o| o.as_ref()) {
+        match sinks.iter().position(|s| &s.name == sink) {
+            Some(i) => used[i] = true,
+            None => unknown_sinks.push(sink.clone()),
+        }
+    }
+
+    let mut out = Vec::new();
+    for (source, sink) in sources.iter().zip(overridden) {
+        match sink {
+            Some(sink) => {
+                out.push(Some(sink));
+                continue;
+            },
+            None => {},
+        }
+        let card = source.card();
+        let found = sinks.iter().enumerate()
+            .find(|&(i, s)| !used[i] && !s.is_monitor() && card.is_some() && s.card() == card)
+            .map(|(i, _)| i);
+        match found {
+            Some(i) => {
+                used[i] = true;
+                out.push(Some(sinks[i].name.clone()));
+            },
+            None => out.push(None),
+        }
+    }
+
+    SinkMatch {
+        sinks: out,
+        unmatched_sinks: sinks.iter().enumerate()
+            .filter(|&(i, s)| !used[i] && !s.is_monitor() && s.bus() == Some("usb"))
+            .map(|(_, s)| s.name.clone())
+            .collect(),
+        unknown_sinks: unknown_sinks,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use devices::device;
+    use super::{match_sinks, parse_overrides};
+
+    #[test]
+    fn test_match_by_card() {
+        let sources = vec![
+            device("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono", &[("device.bus", "usb"), ("alsa.card", "1")]),
+            device("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono.2", &[("device.bus", "usb"), ("alsa.card", "2")]),
+            device("alsa_input.usb-Generic_USB_Ear-Microphone_0000000001-00.analog-stereo", &[("device.bus", "usb"), ("alsa.card", "3")]),
+        ];
+        let sinks = vec![
+            device("alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo.2", &[("device.bus", "usb"), ("alsa.card", "2")]),
+            device("alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo", &[("device.bus", "usb"), ("alsa.card", "1")]),
+            device("alsa_output.usb-Logitech_Logitech_USB_Headset-00.analog-stereo", &[("device.bus", "usb"), ("alsa.card", "4")]),
+        ];
+        let m = match_sinks(&sources, &sinks, &vec![]);
+        assert_eq!(m.sinks, vec![
+            Some(format!("alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo")),
+            Some(format!("alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo.2")),
+            None,
+        ]);
+        assert_eq!(m.unmatched_sinks, vec![format!("alsa_output.usb-Logitech_Logitech_USB_Headset-00.analog-stereo")]);
+
+        let overrides = parse_overrides(&vec![format!("{}={}", sources[2].name, sinks[2].name)]).unwrap();
+        let m = match_sinks(&sources, &sinks, &overrides);
+        assert_eq!(m.sinks[2], Some(sinks[2].name.clone()));
+        assert_eq!(m.unmatched_sinks, Vec::<String>::new());
+        assert_eq!(m.unknown_sinks, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_override_reserves_sink() {
+        let sources = vec![
+            device("alsa_input.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-mono", &[("device.bus", "usb"), ("alsa.card", "1")]),
+            device("alsa_input.usb-Generic_USB_Ear-Microphone_0000000001-00.analog-stereo", &[("device.bus", "usb"), ("alsa.card", "3")]),
+        ];
+        let sinks = vec![
+            device("alsa_output.usb-C-Media_Electronics_Inc._Microsoft_LifeChat_LX-3000-00.analog-stereo", &[("device.bus", "usb"), ("alsa.card", "1")]),
+        ];
+        // the later source is told to use the earlier one's earpiece, the earlier one goes without
+        let overrides = parse_overrides(&vec![format!("{}={}", sources[1].name, sinks[0].name)]).unwrap();
+        let m = match_sinks(&sources, &sinks, &overrides);
+        assert_eq!(m.sinks, vec![None, Some(sinks[0].name.clone())]);
+        assert_eq!(m.unknown_sinks, Vec::<String>::new());
+
+        let overrides = parse_overrides(&vec![format!("{}=alsa_output.missing", sources[1].name)]).unwrap();
+        let m = match_sinks(&sources, &sinks, &overrides);
+        assert_eq!(m.sinks, vec![Some(sinks[0].name.clone()), Some(format!("alsa_output.missing"))]);
+        assert_eq!(m.unknown_sinks, vec![format!("alsa_output.missing")]);
+    }
+
+    #[test]
+    fn test_match_bluetooth() {
+        let bluetooth = |name: &str, address: &str| device(name, &[("device.bus", "bluetooth"), ("device.string", address)]);
+        let sources = vec![
+            bluetooth("bluez_source.00_1B_66_A1_02_03.headset_head_unit", "00:1B:66:A1:02:03"),
+            bluetooth("bluez_source.00_1B_66_A1_02_04.headset_head_unit", "00:1B:66:A1:02:04"),
+        ];
+        let sinks = vec![
+            bluetooth("bluez_sink.00_1B_66_A1_02_04.headset_head_unit", "00:1B:66:A1:02:04"),
+            bluetooth("bluez_sink.00_1B_66_A1_02_03.headset_head_unit", "00:1B:66:A1:02:03"),
+        ];
+        let m = match_sinks(&sources, &sinks, &vec![]);
+        assert_eq!(m.sinks, vec![Some(sinks[1].name.clone()), Some(sinks[0].name.clone())]);
+    }
+
+    #[test]
+    fn test_bad_override() {
+        assert!(parse_overrides(&vec![format!("no-equals-sign")]).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use regex::Regex;
+
+use devices::{Device, DeviceProvider};
+
+
+// Why a source was picked or not, for list-devices
+#[derive(Debug, PartialEq)]
+pub struct Verdict {
+    pub selected: bool,
+    pub reason: String,
+}
+
+
+fn verdict(selected: bool, reason: String) -> Verdict {
+    Verdict {
+        selected: selected,
+        reason: reason,
+    }
+}
+
+
+// Include / exclude regular expressions on the source name. Monitors are always
+// skipped, and by default so is anything not on the usb bus.
+pub struct SourceFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    usb_only: bool,
+}
+
+
+fn compile(patterns: &Vec<String>) -> Result<Vec<Regex>, String> {
+    patterns.iter().map(|p| Regex::new(p).map_err(|e| format!("bad filter `{}`: {}", p, e))).collect()
+}
+
+
+impl SourceFilter {
+    pub fn new(include: &Vec<String>, exclude: &Vec<String>, usb_only: bool) -> Result<SourceFilter, String> {
+        Ok(SourceFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            usb_only: usb_only,
+        })
+    }
+
+    // the first rule that applies wins: monitor, bus, exclude, include
+    pub fn check(&self, source: &Device) -> Verdict {
+        if source.is_monitor() {
+            return verdict(false, format!("monitor source"));
+        }
+        if self.usb_only && source.bus() != Some("usb") {
+            return verdict(false, format!("not a usb device ({}), see --all-buses", source.bus().unwrap_or("unknown bus")));
+        }
+        match self.exclude.iter().find(|re| re.is_match(&source.name)) {
+            Some(re) => return verdict(false, format!("excluded by `{}`", re)),
+            None => {},
+        }
+        if self.include.len() == 0 {
+            return verdict(true, format!("no include filters"));
+        }
+        match self.include.iter().find(|re| re.is_match(&source.name)) {
+            Some(re) => verdict(true, format!("included by `{}`", re)),
+            None => verdict(false, format!("no include filter matched")),
+        }
+    }
+}
+
+
+pub fn get_sources(provider: &DeviceProvider, filter: &SourceFilter) -> Result<Vec<Device>, String>
+{
+    let sources = provider.sources()?;
+    Ok(sources.into_iter().filter(|source| filter.check(source).selected).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use devices::{device, StaticDevices};
+    use super::{get_sources, SourceFilter};
+
+    fn provider() -> StaticDevices {
+        StaticDevices {
+            sources: vec![
+                device("alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono", &[("device.bus", "usb"), ("device.class", "sound")]),
+                device("alsa_output.usb-Logitech_Logitech_USB_Headset-00.analog-stereo.monitor", &[("device.bus", "usb"), ("device.class", "monitor")]),
+                device("alsa_input.pci-0000_00_1b.0.analog-stereo", &[("device.bus", "pci"), ("device.class", "sound")]),
+                device("bluez_source.00_11_22_33_44_55.headset_head_unit", &[("device.bus", "bluetooth"), ("device.class", "sound")]),
+                device("alsa_input.usb-Microsoft_Microsoft_LifeChat_LX-4000-00.analog-stereo", &[("device.bus", "usb"), ("device.class", "sound")]),
+            ],
+            sinks: vec![],
+        }
+    }
+
+    fn names(filter: SourceFilter) -> Vec<String> {
+        get_sources(&provider(), &filter).unwrap().into_iter().map(|d| d.name).collect()
+    }
+
+    #[test]
+    fn test_get_sources() {
+        assert_eq!(names(SourceFilter::new(&vec![], &vec![], true).unwrap()), vec![
+            "alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono",
+            "alsa_input.usb-Microsoft_Microsoft_LifeChat_LX-4000-00.analog-stereo",
+        ]);
+        assert_eq!(names(SourceFilter::new(&vec![format!("Logitech")], &vec![], true).unwrap()), vec![
+            "alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono",
+        ]);
+        assert_eq!(names(SourceFilter::new(&vec![], &vec![format!("Logitech")], true).unwrap()), vec![
+            "alsa_input.usb-Microsoft_Microsoft_LifeChat_LX-4000-00.analog-stereo",
+        ]);
+    }
+
+    #[test]
+    fn test_regex_and_all_buses() {
+        let filter = SourceFilter::new(&vec![format!("^bluez_"), format!("LX-[0-9]+000")], &vec![format!("4000")], false).unwrap();
+        assert_eq!(names(filter), vec![
+            "bluez_source.00_11_22_33_44_55.headset_head_unit",
+        ]);
+        let filter = SourceFilter::new(&vec![], &vec![format!("Microsoft"), format!("^alsa_input\\.pci")], false).unwrap();
+        assert_eq!(names(filter), vec![
+            "alsa_input.usb-Logitech_Logitech_USB_Headset-00.analog-mono",
+            "bluez_source.00_11_22_33_44_55.headset_head_unit",
+        ]);
+    }
+
+    #[test]
+    fn test_verdicts() {
+        let filter = SourceFilter::new(&vec![format!("Logitech")], &vec![format!("pci")], true).unwrap();
+        let reasons = provider().sources.iter().map(|d| filter.check(d).reason).collect::<Vec<String>>();
+        assert_eq!(reasons, vec![
+            "included by `Logitech`",
+            "monitor source",
+            "not a usb device (pci), see --all-buses",
+            "not a usb device (bluetooth), see --all-buses",
+            "no include filter matched",
+        ]);
+        assert!(SourceFilter::new(&vec![format!("(")], &vec![], true).is_err());
+    }
+}
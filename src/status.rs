@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use hub::Voice;
+
+
+// What one voice looks like right now. Levels come from its level watcher, the rest
+// from the hub.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct VoiceStatus {
+    pub id: String,
+    pub rms: Option<f64>, // dB, None until the first level message
+    pub silent: bool,
+    pub s2a: f64,
+    pub a2s: f64,
+    pub amplification: f64,
+    pub muted: bool,
+    pub offline: bool,
+    pub faulted: bool,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+
+// Snapshot of the whole installation, served as json by the control api
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct Status {
+    pub voices: BTreeMap<Voice, VoiceStatus>,
+    pub single: Option<Voice>,
+    pub pairs: Vec<(Voice, Voice)>,  // each pair once, lower voice first
+    pub routes: Vec<(Voice, Voice)>, // speaker -> listener pipelines that are playing
+}
+
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+
+impl Status {
+    pub fn set_level(&mut self, voice: Voice, rms: f64, silent: bool) {
+        let entry = self.voices.entry(voice).or_insert(VoiceStatus::default());
+        entry.rms = Some(rms);
+        entry.silent = silent;
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{FixedOffset, Local, Offset, Utc};
+
+use calibration::Calibration;
+
+
+// The raw levels of one headset, one line per level message, for replaying a night
+// with other settings:
+//
+//   # id H390-1234
+//   # utc_offset 10800
+//   # thresholds -45 -47
+//   1535397791.250 -41.52
+//
+// time is seconds since the epoch, rms in dB. The utc offset, in seconds east, is the
+// local time of the venue when the file was opened, replays report in it. A thresholds
+// line (s2a, a2s) is written whenever the level watcher starts or is reconfigured, the
+// readings after it were judged by those. There is one file per headset id in the trace
+// directory, later runs append to it.
+
+pub struct TraceWriter {
+    path: PathBuf,
+    file: File,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub time: f64,
+    pub rms: f64,
+    pub thresholds: Calibration,
+    pub offset: FixedOffset,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub id: String,
+    pub readings: Vec<Reading>,
+}
+
+
+// headset ids come from the devices, keep them out of other directories
+fn filename(id: &str) -> String {
+    let safe = id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect::<String>();
+    format!("{}.trace", safe)
+}
+
+
+impl TraceWriter {
+    pub fn open(dir: &Path, id: &str) -> Result<TraceWriter, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let path = dir.join(filename(id));
+        let file = OpenOptions::new().create(true).append(true).open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut writer = TraceWriter {
+            path: path,
+            file: file,
+        };
+        writer.line(format!("# id {}\n", id))?;
+        writer.line(format!("# utc_offset {}\n", Local::now().offset().local_minus_utc()))?;
+        Ok(writer)
+    }
+
+    fn line(&mut self, line: String) -> Result<(), String> {
+        self.file.write_all(line.as_bytes()).map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+
+    pub fn thresholds(&mut self, calibration: &Calibration) -> Result<(), String> {
+        self.line(format!("# thresholds {} {}\n", calibration.s2a, calibration.a2s))
+    }
+
+    pub fn level(&mut self, time: SystemTime, rms: f64) -> Result<(), String> {
+        let since = time.duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?;
+        self.line(format!("{}.{:03} {}\n", since.as_secs(), since.subsec_nanos() / 1000000, rms))
+    }
+}
+
+
+fn numbers(words: &[&str]) -> Option<Vec<f64>> {
+    words.iter().map(|w| w.parse::<f64>().ok()).collect()
+}
+
+
+pub fn read(path: &Path) -> Result<Trace, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut id: Option<String> = None;
+    let mut thresholds: Option<Calibration> = None;
+    let mut offset = Utc.fix(); // for a trace without one
+    let mut readings = Vec::new();
+    let cut_short = !text.ends_with('\n');
+    let last = text.lines().count();
+    for (i, line) in text.lines().enumerate() {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let error = |what: &str| format!("{}:{}: {}", path.display(), i + 1, what);
+        match (words.get(0).cloned(), words.get(1).cloned()) {
+            (None, _) => {},
+            (Some("#"), Some("id")) => {
+                let this = words[2..].join(" ");
+                match id {
+                    Some(ref first) if *first != this => return Err(error(&format!("headset {} in the trace of {}", this, first))),
+                    _ => {},
+                }
+                id = Some(this);
+            },
+            (Some("#"), Some("utc_offset")) => match words.get(2).and_then(|w| w.parse::<i32>().ok()).and_then(FixedOffset::east_opt) {
+                Some(east) if words.len() == 3 => offset = east,
+                _ => return Err(error("expected # utc_offset <seconds east>")),
+            },
+            (Some("#"), Some("thresholds")) => match numbers(&words[2..]) {
+                Some(ref n) if n.len() == 2 => thresholds = Some(Calibration { s2a: n[0], a2s: n[1] }),
+                _ => return Err(error("expected # thresholds <s2a> <a2s>")),
+            },
+            (Some("#"), _) => {},
+            _ => match (numbers(&words), thresholds) {
+                (Some(ref n), Some(thresholds)) if n.len() == 2 => readings.push(Reading { time: n[0], rms: n[1], thresholds: thresholds, offset: offset }),
+                (Some(ref n), None) if n.len() == 2 => return Err(error("level before any thresholds")),
+                // the last line of a trace cut short by a crash
+                _ if cut_short && i + 1 == last => {},
+                _ => return Err(error("expected <time> <rms>")),
+            },
+        }
+    }
+    match id {
+        Some(id) => Ok(Trace { id: id, readings: readings }),
+        None => Err(format!("{}: no headset id, not a level trace?", path.display())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+    use chrono::{Local, Offset};
+    use calibration::Calibration;
+    use super::{filename, read, Reading, TraceWriter};
+
+    #[test]
+    fn test_roundtrip() {
+        assert_eq!(filename("../H390 #1"), format!(".._H390__1.trace"));
+        let dir = env::temp_dir().join(format!("egloorator-trace-{}", ::std::process::id()));
+        let calibration = Calibration { s2a: -45f64, a2s: -47.5 };
+        for run in 0..2 {
+            let mut writer = TraceWriter::open(&dir, "H390/1").unwrap();
+            writer.thresholds(&calibration).unwrap();
+            writer.level(UNIX_EPOCH + Duration::from_millis(1535397791250 + run * 1000), -41.5).unwrap();
+        }
+        let path = dir.join("H390_1.trace");
+        let trace = read(&path).unwrap();
+        assert_eq!(trace.id, "H390/1");
+        let offset = Local::now().offset().fix();
+        assert_eq!(trace.readings, vec![Reading { time: 1535397791.25, rms: -41.5, thresholds: calibration, offset: offset },
+                                        Reading { time: 1535397792.25, rms: -41.5, thresholds: calibration, offset: offset }]);
+
+        // cut short
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("{}1535397793.2", text)).unwrap();
+        assert_eq!(read(&path).unwrap().readings.len(), 2);
+        fs::write(&path, "# id a\n1535397793.2 -40\n").unwrap();
+        assert!(read(&path).unwrap_err().ends_with(":2: level before any thresholds"));
+        fs::write(&path, "# id a\n# utc_offset 10800\n# thresholds -45 -47\n1535397793.2 -40\n").unwrap();
+        assert_eq!(read(&path).unwrap().readings[0].offset.local_minus_utc(), 10800);
+        fs::write(&path, "# id a\n# utc_offset +3\n").unwrap();
+        assert!(read(&path).is_ok());
+        fs::write(&path, "# id a\n# utc_offset 3h\n").unwrap();
+        assert!(read(&path).unwrap_err().ends_with(":2: expected # utc_offset <seconds east>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use backend::{Cue, LevelEvent, LevelSource, SharedBackend};
+use backoff::Backoff;
+use calibration::Calibration;
+use config::{Config, CuesConfig, VadConfig};
+use control::Command;
+use headset::{Discovery, Headset};
+use hotplug::DeviceChange;
+use hub::{Message, SilenceChange, Voice};
+use metrics::SharedMetrics;
+use silence::Silence;
+use status::{SharedStatus, VoiceStatus};
+use trace::TraceWriter;
+
+
+// What a level watcher needs from the config. A new set can be handed to a running
+// watcher, it takes effect on the next level message.
+#[derive(Debug, Clone)]
+pub struct WatchParams {
+    calibration: Calibration,
+    vad: VadConfig,
+    cues: CuesConfig,
+}
+
+
+impl WatchParams {
+    pub fn new(headset: &Headset, config: &Config) -> WatchParams {
+        WatchParams {
+            calibration: headset.calibration,
+            vad: config.vad.clone(),
+            cues: config.cues.clone(),
+        }
+    }
+}
+
+
+// why watch_level returned
+enum WatchEnd {
+    Stopped, // asked to stop, or nobody is listening anymore
+    Eos,
+    Error(String),
+}
+
+
+// Everything a watcher shares with the rest of egloorator
+#[derive(Clone)]
+struct Shared {
+    backend: SharedBackend,
+    tx: Sender<Message>,
+    status: SharedStatus,
+    metrics: SharedMetrics,
+    traces: Option<PathBuf>,
+}
+
+
+// A trace that cannot be written to is given up on, the watcher carries on without
+fn traced<F: FnOnce(&mut TraceWriter) -> Result<(), String>>(trace: &mut Option<TraceWriter>, id: &String, f: F) {
+    let result = match *trace {
+        Some(ref mut writer) => f(writer),
+        None => Ok(()),
+    };
+    match result {
+        Ok(()) => {},
+        Err(e) => {
+            error!("{}: level trace: {}", id, e);
+            *trace = None;
+        },
+    }
+}
+
+
+// recovering: the pipeline was restarted after an error, tell the hub once it works
+// params: current settings, updated in place from pending so they survive restarts
+// trace: every reading goes here, with the thresholds it is judged by
+fn watch_level(index: usize, headset: &Headset, source: &mut LevelSource, shared: &Shared, stop: &AtomicBool,
+               mut recovering: bool, params: &mut WatchParams, pending: &Mutex<Option<WatchParams>>,
+               trace: &mut Option<TraceWriter>) -> WatchEnd
+{
+    let tx = &shared.tx;
+    let mut prev = true;
+    info!("{}: s2a {}, a2s {}", headset.id, params.calibration.s2a, params.calibration.a2s);
+    traced(trace, &headset.id, |t| t.thresholds(&params.calibration));
+    let mut silence = Silence::new(params.calibration.s2a, params.calibration.a2s, params.vad.silent_period, params.vad.average_period);
+
+    let mut cue: Option<Box<Cue>> = if params.cues.sine { shared.backend.cue(headset, params.cues.sine_amplitude) } else { None };
+    let mut sine_timeout_counter = 0u64;
+
+    while !stop.load(Ordering::SeqCst) {
+        let rms = match source.next(Duration::from_millis(100)) {
+            None => continue,
+            Some(LevelEvent::Closed) => break,
+            Some(LevelEvent::Error(reason)) => return WatchEnd::Error(reason),
+            Some(LevelEvent::Eos) => {
+                info!("{}: eos received, quitting", headset.id);
+                tx.send(Message::Quit).ok();
+                return WatchEnd::Eos;
+            },
+            Some(LevelEvent::Level(rms)) => rms,
+        };
+        if recovering {
+            info!("{}: level pipeline recovered", headset.id);
+            if tx.send(Message::Recovered(index)).is_err() {
+                break;
+            }
+            recovering = false;
+        }
+        match pending.lock().unwrap().take() {
+            Some(new_params) => {
+                info!("{}: reconfigured, s2a {}, a2s {}", headset.id, new_params.calibration.s2a, new_params.calibration.a2s);
+                silence = silence.reconfigure(new_params.calibration.s2a, new_params.calibration.a2s,
+                                              new_params.vad.silent_period, new_params.vad.average_period);
+                traced(trace, &headset.id, |t| t.thresholds(&new_params.calibration));
+                *params = new_params;
+            },
+            None => {}
+        }
+        traced(trace, &headset.id, |t| t.level(SystemTime::now(), rms));
+        silence = silence.input(rms);
+        trace!("{}: rms = {}", headset.id, rms);
+        let output = silence.output();
+        shared.status.lock().unwrap().set_level(index, rms, output);
+        shared.metrics.level(index, &headset.id, rms, &params.calibration);
+        match (output, output != prev) {
+            (true, true) => {
+                info!("{}: became silent! {}", headset.id, rms);
+                match cue {
+                    Some(ref mut cue) => cue.pause(),
+                    None => {}
+                }
+                if tx.send(Message::Update(SilenceChange{who: index, silent: true}, rms)).is_err() {
+                    break; // coordinator is gone, shutting down
+                }
+            },
+            (false, true) => {
+                info!("{}: became active! {}", headset.id, rms);
+                match cue {
+                    Some(ref mut cue) => {
+                        cue.play();
+                        sine_timeout_counter = params.cues.sine_duration;
+                    },
+                    None => {}
+                }
+                if tx.send(Message::Update(SilenceChange{who: index, silent: false}, rms)).is_err() {
+                    break;
+                }
+            },
+            _ => {
+                match cue {
+                    Some(ref mut cue) => {
+                        if !params.cues.sine_while_active && sine_timeout_counter == 0 {
+                            cue.pause();
+                        }
+                        if sine_timeout_counter > 0 {
+                            sine_timeout_counter -= 1;
+                        }
+                    },
+                    None => {}
+                }
+            },
+        }
+        prev = output;
+    }
+    WatchEnd::Stopped
+}
+
+
+// a watch_level thread, the flag that asks it to stop and its pending new settings
+struct LevelWatcher {
+    stop: Arc<AtomicBool>,
+    pending: Arc<Mutex<Option<WatchParams>>>,
+    handle: thread::JoinHandle<()>,
+}
+
+
+impl LevelWatcher {
+    fn spawn(voice: Voice, headset: Headset, mut params: WatchParams, shared: Shared) -> LevelWatcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let pending = Arc::new(Mutex::new(None));
+        let thread_pending = pending.clone();
+        // the level element is not reconfigured on reload
+        let level_interval = params.vad.level_interval;
+        let handle = thread::spawn(move || {
+            let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+            let mut recovering = false;
+            let mut trace = match shared.traces {
+                Some(ref dir) => match TraceWriter::open(dir, &headset.id) {
+                    Ok(writer) => Some(writer),
+                    Err(e) => {
+                        error!("{}: cannot write level trace: {}", headset.id, e);
+                        None
+                    },
+                },
+                None => None,
+            };
+            while !thread_stop.load(Ordering::SeqCst) {
+                let started = Instant::now();
+                let end = match shared.backend.level_source(&headset, level_interval) {
+                    Ok(mut source) => watch_level(voice, &headset, &mut *source, &shared, &thread_stop, recovering, &mut params, &thread_pending, &mut trace),
+                    Err(e) => WatchEnd::Error(e),
+                };
+                let reason = match end {
+                    WatchEnd::Stopped | WatchEnd::Eos => break,
+                    WatchEnd::Error(reason) => reason,
+                };
+                if started.elapsed() > Duration::from_secs(60) {
+                    backoff.reset();
+                }
+                let delay = backoff.next();
+                warn!("{}: {}, restarting in {}s", headset.id, reason, delay.as_secs());
+                if shared.tx.send(Message::Fault(voice, reason)).is_err() {
+                    break;
+                }
+                let deadline = Instant::now() + delay;
+                while Instant::now() < deadline && !thread_stop.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                recovering = true;
+            }
+        });
+        LevelWatcher {
+            stop: stop,
+            pending: pending,
+            handle: handle,
+        }
+    }
+
+    fn reconfigure(&self, params: WatchParams) {
+        *self.pending.lock().unwrap() = Some(params);
+    }
+
+    fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().unwrap();
+    }
+}
+
+
+// The level watchers of the present headsets, and what the operator changed about
+// them over the control api.
+pub struct Watchers {
+    running: HashMap<Voice, LevelWatcher>,
+    offline: HashSet<Voice>,
+    thresholds: HashMap<Voice, Calibration>, // kept until egloorator restarts
+    shared: Shared,
+}
+
+
+impl Watchers {
+    pub fn new(backend: SharedBackend, tx: Sender<Message>, status: SharedStatus, metrics: SharedMetrics) -> Watchers {
+        Watchers {
+            running: HashMap::new(),
+            offline: HashSet::new(),
+            thresholds: HashMap::new(),
+            shared: Shared {
+                backend: backend,
+                tx: tx,
+                status: status,
+                metrics: metrics,
+                traces: None,
+            },
+        }
+    }
+
+    // headsets started from now on write their rms readings into dir
+    pub fn set_traces(&mut self, dir: &Path) {
+        self.shared.traces = Some(dir.to_path_buf());
+    }
+
+    // the headset as discovered, with any thresholds set by the operator
+    fn headset(&self, voice: Voice, mut headset: Headset) -> Headset {
+        match self.thresholds.get(&voice) {
+            Some(calibration) => headset.calibration = *calibration,
+            None => {}
+        }
+        headset
+    }
+
+    // The hub learns about a new headset before its watcher can report on it
+    fn start(&mut self, voice: Voice, headset: Headset, config: &Config) {
+        let headset = self.headset(voice, headset);
+        self.shared.tx.send(Message::AddHeadset(voice, headset.clone())).ok();
+        let params = WatchParams::new(&headset, config);
+        self.running.insert(voice, LevelWatcher::spawn(voice, headset, params, self.shared.clone()));
+    }
+
+    // and a removed headset's watcher is stopped before the hub forgets it
+    fn stop(&mut self, voice: Voice) {
+        match self.running.remove(&voice) {
+            Some(watcher) => watcher.stop(),
+            None => {}
+        }
+        self.shared.tx.send(Message::RemoveHeadset(voice)).ok();
+    }
+
+    pub fn apply(&mut self, changes: Vec<DeviceChange>, config: &Config) {
+        for change in changes {
+            match change {
+                DeviceChange::Added(voice, headset) => {
+                    info!("{}: headset added as voice {}", headset.id, voice);
+                    if self.offline.contains(&voice) {
+                        info!("{}: voice {} is offline, not starting it", headset.id, voice);
+                    } else {
+                        self.start(voice, headset, config);
+                    }
+                },
+                DeviceChange::Removed(voice, headset) => {
+                    info!("{}: headset removed, was voice {}", headset.id, voice);
+                    self.stop(voice);
+                },
+            }
+        }
+    }
+
+    // new settings for every running watcher, and the hub
+    pub fn reconfigure(&self, present: &BTreeMap<Voice, Headset>, discovery: &Discovery, config: &Config) {
+        for (voice, headset) in present {
+            match self.running.get(voice) {
+                Some(watcher) => {
+                    let headset = self.headset(*voice, discovery.headset(headset.id.clone(), headset.source.clone(), headset.sink.clone()));
+                    watcher.reconfigure(WatchParams::new(&headset, config));
+                    self.shared.tx.send(Message::UpdateHeadset(*voice, headset)).ok();
+                },
+                None => {}
+            }
+        }
+    }
+
+    // commands about levels and whole headsets are handled here, pairing is up to the hub
+    pub fn command(&mut self, command: Command, present: &BTreeMap<Voice, Headset>, discovery: &mut Discovery, config: &Config) {
+        info!("control: {:?}", command);
+        let tx = self.shared.tx.clone();
+        match command {
+            Command::Connect(one, two) => { tx.send(Message::ForceConnect(one, two)).ok(); },
+            Command::Disconnect(voice) => { tx.send(Message::ForceDisconnect(voice)).ok(); },
+            Command::Mute(voice, muted) => { tx.send(Message::Mute(voice, muted)).ok(); },
+            Command::Thresholds(voice, calibration) => {
+                self.thresholds.insert(voice, calibration);
+                self.reconfigure(present, discovery, config);
+            },
+            Command::SaveCalibration(voice) => {
+                match present.get(&voice) {
+                    Some(headset) => {
+                        let headset = self.headset(voice, discovery.headset(headset.id.clone(), headset.source.clone(), headset.sink.clone()));
+                        discovery.calibration.set(&headset.id, headset.calibration);
+                        match discovery.calibration.save() {
+                            Ok(()) => info!("{}: saved s2a {}, a2s {}", headset.id, headset.calibration.s2a, headset.calibration.a2s),
+                            Err(e) => error!("{}: cannot save calibration: {}", headset.id, e),
+                        }
+                    },
+                    None => {}
+                }
+            },
+            Command::Offline(voice, true) => {
+                if self.offline.insert(voice) {
+                    self.shared.status.lock().unwrap().voices.entry(voice).or_insert(VoiceStatus::default()).offline = true;
+                    self.stop(voice);
+                }
+            },
+            Command::Offline(voice, false) => {
+                if self.offline.remove(&voice) {
+                    match self.shared.status.lock().unwrap().voices.get_mut(&voice) {
+                        Some(entry) => entry.offline = false,
+                        None => {}
+                    }
+                    match present.get(&voice) {
+                        Some(headset) => {
+                            let headset = discovery.headset(headset.id.clone(), headset.source.clone(), headset.sink.clone());
+                            self.start(voice, headset, config);
+                        },
+                        None => {}
+                    }
+                }
+            },
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        for (_, watcher) in self.running.drain() {
+            watcher.stop();
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use base64;
+use sha1::Sha1;
+
+
+// Just enough of rfc 6455 to push text to browsers: the handshake answer and
+// unmasked, unfragmented server frames. Whatever the client sends is ignored.
+
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+
+// the Sec-WebSocket-Accept value for a Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+
+pub fn text_frame(text: &str) -> Vec<u8> {
+    let payload = text.as_bytes();
+    let mut frame = vec![0x81u8]; // fin, text
+    let len = payload.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len < 65536 {
+        frame.push(126);
+        frame.push((len >> 8) as u8);
+        frame.push(len as u8);
+    } else {
+        frame.push(127);
+        for i in (0..8).rev() {
+            frame.push((len as u64 >> (8 * i)) as u8);
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, text_frame};
+
+    #[test]
+    fn test_accept_key() {
+        // the example from rfc 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_text_frame() {
+        assert_eq!(text_frame("Hello"), vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        let long = "x".repeat(300);
+        assert_eq!(&text_frame(&long)[..4], &[0x81, 126, 1, 44]);
+        assert_eq!(text_frame(&long).len(), 304);
+    }
+}
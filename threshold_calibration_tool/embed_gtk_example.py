@@ -1,35 +0,0 @@
-#!/usr/bin/env python3
-"""
-show how to add a matplotlib FigureCanvasGTK or FigureCanvasGTKAgg widget to a
-gtk.Window
-"""
-
-import gi
-gi.require_version('Gtk', '3.0')
-from gi.repository import Gtk
-
-from matplotlib.figure import Figure
-from numpy import arange, sin, pi
-
-# uncomment to select /GTK/GTKAgg/GTKCairo, but only cairo works :|
-from matplotlib.backends.backend_gtk3cairo import FigureCanvas
-#from matplotlib.backends.backend_gtk3 import FigureCanvas
-#from matplotlib.backends.backend_gtk3agg import FigureCanvas
-
-
-win = Gtk.Window()
-win.connect("destroy", lambda x: Gtk.main_quit())
-win.set_default_size(400, 300)
-win.set_title("Embedding in GTK")
-
-f = Figure(figsize=(5, 4), dpi=100)
-a = f.add_subplot(111)
-t = arange(0.0, 3.0, 0.01)
-s = sin(2*pi*t)
-a.plot(t, s)
-
-canvas = FigureCanvas(f)  # a gtk.DrawingArea
-win.add(canvas)
-
-win.show_all()
-Gtk.main()